@@ -0,0 +1,435 @@
+use std::io::BufRead;
+use std::os::unix::fs::symlink;
+use std::process::Command;
+
+use alpm::{
+    Alpm, AnyEvent, AnyQuestion, CommitData, Event, LogLevel, PrepareData, Progress, SigLevel,
+    TransFlag,
+};
+
+use crate::keyring;
+use crate::pacman::{self, PendingUpdate};
+use crate::pacman_conf::{self, PacmanConf};
+use crate::prefetch;
+use crate::runner;
+use crate::transaction::{self, Question};
+
+pub const HELPER_ARG: &str = "--alpm-transaction";
+
+const ROOT: &str = "/";
+const DB_PATH: &str = "/var/lib/pacman";
+const GPG_DIR: &str = "/etc/pacman.d/gnupg";
+const LOG_FILE: &str = "/var/log/pacman.log";
+const HOOK_DIRS: [&str; 2] = ["/usr/share/libalpm/hooks", "/etc/pacman.d/hooks"];
+
+// Sets a handle up the way pacman would from /etc/pacman.conf.
+fn handle(db_path: &str) -> Result<Alpm, String> {
+    let conf = PacmanConf::load().map_err(|e| format!("Cannot read {}: {}", pacman_conf::PATH, e))?;
+    let mut handle = Alpm::new(ROOT, db_path).map_err(|e| e.to_string())?;
+
+    let architectures = match conf.option("Architecture").as_deref() {
+        None | Some("auto") => vec![machine()],
+        Some(value) => value.split_whitespace().map(str::to_string).collect(),
+    };
+    for architecture in &architectures {
+        handle.add_architecture(architecture.as_str()).map_err(|e| e.to_string())?;
+    }
+
+    handle
+        .add_cachedir(conf.option("CacheDir").unwrap_or_else(|| prefetch::SYSTEM_CACHE.to_string()))
+        .map_err(|e| e.to_string())?;
+    handle.set_gpgdir(conf.option("GPGDir").unwrap_or_else(|| GPG_DIR.to_string())).map_err(|e| e.to_string())?;
+    handle.set_logfile(conf.option("LogFile").unwrap_or_else(|| LOG_FILE.to_string())).map_err(|e| e.to_string())?;
+    for dir in HOOK_DIRS {
+        handle.add_hookdir(dir).map_err(|e| e.to_string())?;
+    }
+    for name in conf.option("IgnorePkg").unwrap_or_default().split_whitespace() {
+        handle.add_ignorepkg(name).map_err(|e| e.to_string())?;
+    }
+    if let Some(count) = conf.option("ParallelDownloads").and_then(|value| value.parse().ok()) {
+        handle.set_parallel_downloads(count);
+    }
+
+    let default_level = parse_sig_level(
+        &conf.option("SigLevel").unwrap_or_default(),
+        SigLevel::PACKAGE | SigLevel::DATABASE | SigLevel::DATABASE_OPTIONAL,
+    );
+    handle.set_default_siglevel(default_level).map_err(|e| e.to_string())?;
+
+    for repo in conf.repos().into_iter().filter(|repo| repo.enabled) {
+        let level = parse_sig_level(repo.sig_level.as_deref().unwrap_or(""), default_level);
+        let db = handle.register_syncdb_mut(repo.name.as_str(), level).map_err(|e| e.to_string())?;
+        for server in &repo.servers {
+            let server = server.replace("$repo", &repo.name).replace("$arch", &architectures[0]);
+            db.add_server(server).map_err(|e| e.to_string())?;
+        }
+    }
+
+    Ok(handle)
+}
+
+fn machine() -> String {
+    runner::command("uname")
+        .arg("-m")
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_else(|_| "x86_64".to_string())
+}
+
+// pacman.conf's SigLevel syntax, e.g. "Required DatabaseOptional" or
+// "PackageTrustAll", applied on top of `level`.
+fn parse_sig_level(value: &str, mut level: SigLevel) -> SigLevel {
+    for token in value.split_whitespace() {
+        let (scopes, token) = if let Some(token) = token.strip_prefix("Package") {
+            (&[true, false], token)
+        } else if let Some(token) = token.strip_prefix("Database") {
+            (&[false, true], token)
+        } else {
+            (&[true, true], token)
+        };
+
+        let flags = [
+            (SigLevel::PACKAGE, SigLevel::PACKAGE_OPTIONAL, SigLevel::PACKAGE_MARGINAL_OK | SigLevel::PACKAGE_UNKNOWN_OK),
+            (SigLevel::DATABASE, SigLevel::DATABASE_OPTIONAL, SigLevel::DATABASE_MARGINAL_OK | SigLevel::DATABASE_UNKNOWN_OK),
+        ];
+        for (&applies, (check, optional, trust)) in scopes.iter().zip(flags) {
+            if !applies {
+                continue;
+            }
+            match token {
+                "Never" => level.remove(check | optional),
+                "Optional" => level.insert(check | optional),
+                "Required" => {
+                    level.insert(check);
+                    level.remove(optional);
+                }
+                "TrustedOnly" => level.remove(trust),
+                "TrustAll" => level.insert(trust),
+                _ => {}
+            }
+        }
+    }
+    level
+}
+
+// Same as checkupdates: the sync databases are refreshed in a throwaway path
+// next to the real local database, so this needs no root.
+pub fn pending_updates() -> Result<Vec<PendingUpdate>, String> {
+    let db_path = pacman::checkupdates_dbpath().unwrap_or_else(|| {
+        let tmp = std::env::var("TMPDIR").unwrap_or_else(|_| "/tmp".to_string());
+        format!("{}/checkup-db-{}", tmp, uid())
+    });
+    std::fs::create_dir_all(&db_path).map_err(|e| format!("Cannot create {}: {}", db_path, e))?;
+    let local = std::path::Path::new(&db_path).join("local");
+    if !local.exists() {
+        symlink(format!("{}/local", DB_PATH), &local).map_err(|e| e.to_string())?;
+    }
+
+    let mut handle = handle(&db_path)?;
+    handle.syncdbs_mut().update(false).map_err(|e| format!("failed to synchronize all databases ({})", e))?;
+
+    let security = pacman::security_updates();
+    let mut updates = Vec::new();
+    for installed in handle.localdb().pkgs() {
+        let Some(new) = installed.sync_new_version(handle.syncdbs()) else {
+            continue;
+        };
+        updates.push(PendingUpdate {
+            name: installed.name().to_string(),
+            old_version: installed.version().to_string(),
+            new_version: new.version().to_string(),
+            repo: new.db().map(|db| db.name().to_string()).unwrap_or_default(),
+            download_size: new.download_size().max(0) as u64,
+            installed_delta: new.isize() - installed.isize(),
+            security: security.iter().any(|name| name == installed.name()),
+        });
+    }
+    Ok(updates)
+}
+
+fn uid() -> u32 {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata("/proc/self").map(|metadata| metadata.uid()).unwrap_or(0)
+}
+
+// `pkexec updater-new --alpm-transaction`, run through transaction::run.
+pub fn command(packages: &[String], sysupgrade: bool, pending: &[String], ignored: &[String]) -> Command {
+    let mut command = runner::command("pkexec");
+    command.arg(std::env::current_exe().unwrap_or_else(|_| "updater-new".into()));
+    command.arg(HELPER_ARG);
+    if sysupgrade {
+        command.arg("--sysupgrade");
+    }
+    let keyring_pending = |names: &[String]| names.iter().any(|name| name == keyring::KEYRING_PACKAGE);
+    if keyring_pending(pending) && !keyring_pending(ignored) {
+        command.arg("--keyring-first");
+    }
+    command.args(prefetch::cachedir_args());
+    for name in ignored {
+        command.args(["--ignore", name]);
+    }
+    command.args(packages);
+    command
+}
+
+#[derive(Default)]
+struct HelperArgs {
+    sysupgrade: bool,
+    keyring_first: bool,
+    cachedirs: Vec<String>,
+    ignored: Vec<String>,
+    packages: Vec<String>,
+}
+
+// The privileged side. Questions and progress go to stdout as
+// transaction::MESSAGE_PREFIX lines, answers come back on stdin. Errors are
+// printed in pacman's wording so errors::PacmanError can classify them.
+pub fn run_helper(args: &[String]) -> i32 {
+    let mut parsed = HelperArgs::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--sysupgrade" => parsed.sysupgrade = true,
+            "--keyring-first" => parsed.keyring_first = true,
+            "--cachedir" => parsed.cachedirs.extend(args.next().cloned()),
+            "--ignore" => parsed.ignored.extend(args.next().cloned()),
+            _ if arg.starts_with('-') => {
+                eprintln!("{} does not take {}", HELPER_ARG, arg);
+                return 2;
+            }
+            _ => parsed.packages.push(arg.clone()),
+        }
+    }
+
+    let mut result = sync_databases(&parsed);
+    let keyring_held = parsed.ignored.iter().any(|name| name == keyring::KEYRING_PACKAGE);
+    if result.is_ok() && parsed.keyring_first && !keyring_held {
+        let keyring_only = HelperArgs {
+            sysupgrade: false,
+            packages: vec![keyring::KEYRING_PACKAGE.to_string()],
+            cachedirs: parsed.cachedirs.clone(),
+            ignored: parsed.ignored.clone(),
+            ..Default::default()
+        };
+        result = commit(&keyring_only);
+    }
+    if result.is_ok() {
+        result = commit(&parsed);
+    }
+
+    match result {
+        Ok(()) => 0,
+        Err(error) => {
+            println!("error: {}", error);
+            1
+        }
+    }
+}
+
+fn sync_databases(args: &HelperArgs) -> Result<(), String> {
+    if !args.sysupgrade {
+        return Ok(());
+    }
+    println!(":: Synchronizing package databases...");
+    let mut handle = handle(DB_PATH)?;
+    set_callbacks(&handle);
+    handle
+        .syncdbs_mut()
+        .update(false)
+        .map(|_| ())
+        .map_err(|e| format!("failed to synchronize all databases ({})", e))
+}
+
+fn commit(args: &HelperArgs) -> Result<(), String> {
+    let mut handle = handle(DB_PATH)?;
+    for dir in &args.cachedirs {
+        handle.add_cachedir(dir.as_str()).map_err(|e| e.to_string())?;
+    }
+    for name in &args.ignored {
+        handle.add_ignorepkg(name.as_str()).map_err(|e| e.to_string())?;
+    }
+    set_callbacks(&handle);
+
+    handle
+        .trans_init(TransFlag::NONE)
+        .map_err(|e| format!("failed to init transaction ({})", e))?;
+    let result = prepare_and_commit(&mut handle, args);
+    let _ = handle.trans_release();
+    result
+}
+
+fn prepare_and_commit(handle: &mut Alpm, args: &HelperArgs) -> Result<(), String> {
+    if args.sysupgrade {
+        handle.sync_sysupgrade(false).map_err(|e| e.to_string())?;
+    }
+    for name in &args.packages {
+        let package = handle
+            .syncdbs()
+            .find_satisfier(name.as_str())
+            .ok_or_else(|| format!("target not found: {}", name))?;
+        if let Err(e) = handle.trans_add_pkg(package) {
+            // Already part of the upgrade.
+            if e.error != alpm::Error::TransDupTarget {
+                return Err(format!("'{}': {}", name, e));
+            }
+        }
+    }
+
+    if let Err(e) = handle.trans_prepare() {
+        let mut lines = vec![format!("failed to prepare transaction ({})", e)];
+        match e.data() {
+            Some(PrepareData::UnsatisfiedDeps(missing)) => {
+                for dep in missing {
+                    let required_by = dep.causing_pkg().unwrap_or(dep.target());
+                    lines.push(format!(":: unable to satisfy dependency '{}' required by {}", dep.depend(), required_by));
+                }
+            }
+            Some(PrepareData::ConflictingDeps(conflicts)) => {
+                for conflict in conflicts {
+                    lines.push(format!(":: {} and {} are in conflict", conflict.package1().name(), conflict.package2().name()));
+                }
+            }
+            Some(PrepareData::PkgInvalidArch(packages)) => {
+                for package in packages {
+                    lines.push(format!(":: package {} does not have a valid architecture", package.name()));
+                }
+            }
+            None => {}
+        }
+        return Err(lines.join("\n"));
+    }
+
+    if handle.trans_add().is_empty() && handle.trans_remove().is_empty() {
+        println!(" there is nothing to do");
+        return Ok(());
+    }
+
+    if let Err(e) = handle.trans_commit() {
+        let mut lines = vec![format!("failed to commit transaction ({})", e)];
+        // alpm 5 hands file conflicts out with the wrong element type, they
+        // are only reported by count here.
+        if let Some(CommitData::PkgInvalid(files)) = e.data() {
+            for file in files {
+                lines.push(format!("{} is invalid or corrupted", file));
+            }
+        }
+        return Err(lines.join("\n"));
+    }
+    Ok(())
+}
+
+fn set_callbacks(handle: &Alpm) {
+    handle.set_log_cb((), |level, message, _| {
+        if level.contains(LogLevel::ERROR) {
+            print!("error: {}", message);
+        } else if level.contains(LogLevel::WARNING) {
+            print!("warning: {}", message);
+        }
+    });
+    handle.set_event_cb((), |event: AnyEvent, _| match event.event() {
+        Event::PkgRetrieveStart(_) => {
+            println!(":: Retrieving packages...");
+            println!("{}", transaction::progress_message(0, "Downloading packages"));
+        }
+        Event::TransactionStart => println!(":: Processing package changes..."),
+        Event::ScriptletInfo(info) => print!("{}", info.line()),
+        Event::HookRunStart(hook) => {
+            println!("({}/{}) {}", hook.position(), hook.total(), hook.desc().unwrap_or(hook.name()));
+        }
+        _ => {}
+    });
+    // Percentages repeat a lot, only changes are passed on.
+    handle.set_progress_cb(String::new(), |progress, package, percent, total, current, last| {
+        let action = match progress {
+            Progress::AddStart => "Installing",
+            Progress::UpgradeStart => "Upgrading",
+            Progress::DowngradeStart => "Downgrading",
+            Progress::ReinstallStart => "Reinstalling",
+            Progress::RemoveStart => "Removing",
+            Progress::ConflictsStart => "Checking for file conflicts",
+            Progress::DiskspaceStart => "Checking available disk space",
+            Progress::IntegrityStart => "Checking package integrity",
+            Progress::LoadStart => "Loading package files",
+            Progress::KeyringStart => "Checking keys in keyring",
+        };
+        let text = if package.is_empty() {
+            action.to_string()
+        } else {
+            format!("{} {} ({}/{})", action, package, current, total)
+        };
+        let message = transaction::progress_message(percent.clamp(0, 100) as u32, &text);
+        if *last != message {
+            println!("{}", message);
+            *last = message;
+        }
+    });
+    handle.set_question_cb((), |question: AnyQuestion, _| answer(question));
+}
+
+fn answer(question: AnyQuestion) {
+    match question.question() {
+        alpm::Question::InstallIgnorepkg(mut question) => {
+            let text = format!("{} is in IgnorePkg/IgnoreGroup. Install anyway?", question.pkg().name());
+            question.set_install(confirm(text, true));
+        }
+        alpm::Question::Replace(question) => {
+            let text = format!("Replace {} with {}/{}?",
+                question.oldpkg().name(), question.newdb().name(), question.newpkg().name());
+            question.set_replace(confirm(text, true));
+        }
+        alpm::Question::Conflict(mut question) => {
+            let conflict = question.conflict();
+            let (first, second) = (conflict.package1().name(), conflict.package2().name());
+            let reason = conflict.reason().name();
+            let text = if reason == first || reason == second {
+                format!("{} and {} are in conflict. Remove {}?", first, second, second)
+            } else {
+                format!("{} and {} are in conflict ({}). Remove {}?", first, second, reason, second)
+            };
+            question.set_remove(confirm(text, false));
+        }
+        alpm::Question::Corrupted(mut question) => {
+            let text = format!("File {} is corrupted ({}). Do you want to delete it?", question.filepath(), question.reason());
+            question.set_remove(confirm(text, true));
+        }
+        alpm::Question::RemovePkgs(mut question) => {
+            let names: Vec<&str> = question.packages().iter().map(|package| package.name()).collect();
+            let text = format!(
+                "The following packages cannot be upgraded due to unresolvable dependencies: {}. \
+                 Do you want to skip the above packages for this upgrade?",
+                names.join(" "));
+            question.set_skip(confirm(text, false));
+        }
+        alpm::Question::SelectProvider(mut question) => {
+            let options: Vec<String> = question
+                .providers()
+                .iter()
+                .map(|package| match package.db() {
+                    Some(db) => format!("{}/{}", db.name(), package.name()),
+                    None => package.name().to_string(),
+                })
+                .collect();
+            let text = format!("There are {} providers available for {}", options.len(), question.depend());
+            let choice = ask(Question::Choose { text, options, default: 0 });
+            let index = choice.parse::<i32>().unwrap_or(1).max(1) - 1;
+            question.set_index(index);
+        }
+        alpm::Question::ImportKey(mut question) => {
+            let text = format!("Import PGP key {}, \"{}\"?", question.fingerprint(), question.uid());
+            question.set_import(confirm(text, true));
+        }
+    }
+}
+
+fn confirm(text: String, default: bool) -> bool {
+    ask(Question::Confirm { text, default }).eq_ignore_ascii_case("y")
+}
+
+fn ask(question: Question) -> String {
+    println!("{}", question.to_message());
+    let mut answer = String::new();
+    match std::io::stdin().lock().read_line(&mut answer) {
+        Ok(read) if read > 0 => answer.trim().to_string(),
+        _ => question.default_answer(),
+    }
+}
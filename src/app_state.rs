@@ -0,0 +1,295 @@
+use gtk::{gio, glib};
+use gtk::prelude::*;
+
+pub const DBUS_INTERFACE: &str = "org.gnome.Example.Updater";
+
+const DBUS_XML: &str = r#"
+<node>
+  <interface name="org.gnome.Example.Updater">
+    <property name="State" type="s" access="read"/>
+    <property name="PendingUpdates" type="u" access="read"/>
+    <property name="Error" type="s" access="read"/>
+  </interface>
+</node>
+"#;
+
+// What the window is doing, the label, the buttons and the D-Bus
+// properties all follow from it.
+#[derive(Debug, Default, Clone, PartialEq, Eq, glib::Boxed)]
+#[boxed_type(name = "UpdaterAppState")]
+pub enum AppState {
+    #[default]
+    Idle,
+    Checking,
+    UpToDate,
+    UpdatesAvailable(u32),
+    Updating,
+    // What to tell the user, e.g. the title of the classified pacman error.
+    Failed(String),
+    RebootRequired,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateEvent {
+    CheckStarted,
+    CheckFinished(u32),
+    CheckFailed(String),
+    UpdateStarted,
+    UpdateFinished,
+    UpdateFailed(String),
+    // The running kernel was upgraded.
+    RebootNeeded,
+    // An offline update waits for the next boot, its packages still show up
+    // as pending until then.
+    UpdateScheduled,
+}
+
+impl AppState {
+    // None when the event makes no sense in this state, e.g. a background
+    // check while an update is running.
+    pub fn next(&self, event: StateEvent) -> Option<AppState> {
+        use AppState::*;
+        match (self, event) {
+            (Checking | Updating, StateEvent::CheckStarted) => None,
+            (_, StateEvent::CheckStarted) => Some(Checking),
+
+            (Checking, StateEvent::CheckFinished(0)) => Some(UpToDate),
+            (Checking, StateEvent::CheckFinished(count)) => Some(UpdatesAvailable(count)),
+            (Checking, StateEvent::CheckFailed(reason)) => Some(Failed(reason)),
+            (_, StateEvent::CheckFinished(_) | StateEvent::CheckFailed(_)) => None,
+
+            (Idle | UpToDate | UpdatesAvailable(_) | Failed(_), StateEvent::UpdateStarted) => Some(Updating),
+            (_, StateEvent::UpdateStarted) => None,
+
+            (Updating, StateEvent::UpdateFinished) => Some(UpToDate),
+            (Updating, StateEvent::UpdateFailed(reason)) => Some(Failed(reason)),
+            (_, StateEvent::UpdateFinished | StateEvent::UpdateFailed(_)) => None,
+
+            // A check or an update that is still running reports it later.
+            (Checking | Updating, StateEvent::RebootNeeded) => None,
+            (Idle | UpToDate | RebootRequired, StateEvent::RebootNeeded) => Some(RebootRequired),
+            // Pending updates or a failure matter more than the restart.
+            (UpdatesAvailable(_) | Failed(_), StateEvent::RebootNeeded) => None,
+
+            (Checking | Updating, StateEvent::UpdateScheduled) => None,
+            (_, StateEvent::UpdateScheduled) => Some(RebootRequired),
+        }
+    }
+
+    pub fn is_busy(&self) -> bool {
+        matches!(self, AppState::Checking | AppState::Updating)
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            AppState::Idle => "Ready".to_string(),
+            AppState::Checking => "Checking for updates...".to_string(),
+            AppState::UpToDate => "System up to date".to_string(),
+            AppState::UpdatesAvailable(1) => "1 update available".to_string(),
+            AppState::UpdatesAvailable(count) => format!("{} updates available", count),
+            AppState::Updating => "Updating All...".to_string(),
+            AppState::Failed(reason) => reason.clone(),
+            AppState::RebootRequired => "Restart to finish the update".to_string(),
+        }
+    }
+
+    // The value of the State property on D-Bus.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AppState::Idle => "idle",
+            AppState::Checking => "checking",
+            AppState::UpToDate => "up-to-date",
+            AppState::UpdatesAvailable(_) => "updates-available",
+            AppState::Updating => "updating",
+            AppState::Failed(_) => "failed",
+            AppState::RebootRequired => "reboot-required",
+        }
+    }
+
+    fn dbus_property(&self, name: &str) -> Option<glib::Variant> {
+        match name {
+            "State" => Some(self.name().to_variant()),
+            "PendingUpdates" => Some(match self {
+                AppState::UpdatesAvailable(count) => *count,
+                _ => 0,
+            }.to_variant()),
+            "Error" => Some(match self {
+                AppState::Failed(reason) => reason.as_str(),
+                _ => "",
+            }.to_variant()),
+            _ => None,
+        }
+    }
+}
+
+// Publishes the state next to the application's own D-Bus object, so that
+// panel applets and scripts can read it without polling pacman.
+#[derive(Debug)]
+pub struct StateExport {
+    connection: gio::DBusConnection,
+    path: String,
+    registration: Option<gio::RegistrationId>,
+}
+
+impl StateExport {
+    pub fn register(
+        connection: &gio::DBusConnection,
+        path: &str,
+        current: impl Fn() -> AppState + 'static,
+    ) -> Option<Self> {
+        let interface = gio::DBusNodeInfo::for_xml(DBUS_XML)
+            .ok()?
+            .lookup_interface(DBUS_INTERFACE)?;
+        let registration = connection
+            .register_object(path, &interface)
+            .property(move |_, _, _, _, name| {
+                current().dbus_property(name).unwrap_or_else(|| "".to_variant())
+            })
+            .build();
+        match registration {
+            Ok(registration) => Some(Self {
+                connection: connection.clone(),
+                path: path.to_string(),
+                registration: Some(registration),
+            }),
+            Err(e) => {
+                eprintln!("Failed to export the state on D-Bus: {}", e);
+                None
+            }
+        }
+    }
+
+    pub fn changed(&self, state: &AppState) {
+        let changed = glib::VariantDict::new(None);
+        for name in ["State", "PendingUpdates", "Error"] {
+            if let Some(value) = state.dbus_property(name) {
+                changed.insert_value(name, &value);
+            }
+        }
+        let parameters = (DBUS_INTERFACE, changed.end(), Vec::<String>::new()).to_variant();
+        if let Err(e) = self.connection.emit_signal(
+            None,
+            &self.path,
+            "org.freedesktop.DBus.Properties",
+            "PropertiesChanged",
+            Some(&parameters),
+        ) {
+            eprintln!("Failed to announce the state on D-Bus: {}", e);
+        }
+    }
+}
+
+impl Drop for StateExport {
+    fn drop(&mut self) {
+        if let Some(registration) = self.registration.take() {
+            let _ = self.connection.unregister_object(registration);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use AppState::*;
+
+    fn states() -> [AppState; 7] {
+        [
+            Idle,
+            Checking,
+            UpToDate,
+            UpdatesAvailable(3),
+            Updating,
+            Failed("No network".to_string()),
+            RebootRequired,
+        ]
+    }
+
+    // Where `event` leads from every state, in the order of states().
+    fn outcomes(event: StateEvent) -> Vec<Option<AppState>> {
+        states().iter().map(|state| state.next(event.clone())).collect()
+    }
+
+    #[test]
+    fn check_started() {
+        assert_eq!(outcomes(StateEvent::CheckStarted), [
+            Some(Checking),
+            None,
+            Some(Checking),
+            Some(Checking),
+            None,
+            Some(Checking),
+            Some(Checking),
+        ]);
+    }
+
+    #[test]
+    fn check_finished() {
+        assert_eq!(outcomes(StateEvent::CheckFinished(0)), [None, Some(UpToDate), None, None, None, None, None]);
+        assert_eq!(
+            outcomes(StateEvent::CheckFinished(2)),
+            [None, Some(UpdatesAvailable(2)), None, None, None, None, None],
+        );
+    }
+
+    #[test]
+    fn check_failed() {
+        let failed = Failed("Mirror unreachable".to_string());
+        assert_eq!(
+            outcomes(StateEvent::CheckFailed("Mirror unreachable".to_string())),
+            [None, Some(failed), None, None, None, None, None],
+        );
+    }
+
+    #[test]
+    fn update_started() {
+        assert_eq!(outcomes(StateEvent::UpdateStarted), [
+            Some(Updating),
+            None,
+            Some(Updating),
+            Some(Updating),
+            None,
+            Some(Updating),
+            None,
+        ]);
+    }
+
+    #[test]
+    fn update_finished() {
+        assert_eq!(outcomes(StateEvent::UpdateFinished), [None, None, None, None, Some(UpToDate), None, None]);
+    }
+
+    #[test]
+    fn update_failed() {
+        let failed = Failed("2 conflicting files".to_string());
+        assert_eq!(
+            outcomes(StateEvent::UpdateFailed("2 conflicting files".to_string())),
+            [None, None, None, None, Some(failed), None, None],
+        );
+    }
+
+    #[test]
+    fn reboot_needed() {
+        assert_eq!(outcomes(StateEvent::RebootNeeded), [
+            Some(RebootRequired),
+            None,
+            Some(RebootRequired),
+            None,
+            None,
+            None,
+            Some(RebootRequired),
+        ]);
+    }
+
+    #[test]
+    fn update_scheduled() {
+        assert_eq!(outcomes(StateEvent::UpdateScheduled), [
+            Some(RebootRequired),
+            None,
+            Some(RebootRequired),
+            Some(RebootRequired),
+            None,
+            Some(RebootRequired),
+            Some(RebootRequired),
+        ]);
+    }
+}
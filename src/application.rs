@@ -45,6 +45,7 @@ mod imp {
             let obj = self.obj();
             obj.setup_gactions();
             obj.set_accels_for_action("app.quit", &["<control>q"]);
+            obj.set_accels_for_action("win.search", &["<control>f"]);
         }
     }
 
@@ -104,7 +105,7 @@ impl UpdaterNewApplication {
             .version(VERSION)
             .developers(vec!["IttsMichael"])
             // Translators: Replace "translator-credits" with your name/username, and optionally an email or URL.
-            .translator_credits(&gettext("translator-credits"))
+            .translator_credits(gettext("translator-credits"))
             .copyright("© 2026 IttsMichael")
             .build();
 
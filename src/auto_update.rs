@@ -0,0 +1,426 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use gtk::{gio, glib};
+use gtk::prelude::*;
+
+use crate::conditions::{Activity, Conditions};
+use crate::fleet::{self, FleetConfig, FleetEvent};
+use crate::holds::{self, Holds};
+use crate::keyring;
+use crate::pacman;
+use crate::report::Report;
+use crate::runner;
+
+pub const NEWS_FEED: &str = "https://archlinux.org/feeds/news/";
+pub const NEWS_PAGE: &str = "https://archlinux.org/news/";
+
+// `updater-new --auto-update`, started every few minutes by
+// updater-new-auto-update.timer in the user's session.
+pub const CLI_ARG: &str = "--auto-update";
+// The privileged side, `pkexec updater-new --auto-upgrade-helper`. The polkit
+// rule we ship lets members of wheel run it without a password.
+pub const HELPER_ARG: &str = "--auto-upgrade-helper";
+
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+// A weekly maintenance window such as "Sun 02:00-04:00", "Mon,Thu 22:00-01:00"
+// or "Daily 03:00-05:00". Windows may run past midnight.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaintenanceWindow {
+    // 1 = Monday .. 7 = Sunday, like glib::DateTime::day_of_week.
+    pub days: Vec<i32>,
+    pub start: i32,
+    pub end: i32,
+}
+
+impl MaintenanceWindow {
+    pub fn parse(text: &str) -> Option<Self> {
+        let (days, times) = text.trim().split_once(' ')?;
+
+        let days = if days.eq_ignore_ascii_case("daily") {
+            (1..=7).collect()
+        } else {
+            days.split(',')
+                .map(|day| {
+                    let day = day.trim().to_lowercase();
+                    DAYS.iter().position(|name| day.starts_with(name)).map(|index| index as i32 + 1)
+                })
+                .collect::<Option<Vec<i32>>>()?
+        };
+
+        let (start, end) = times.trim().split_once('-')?;
+        Some(Self {
+            days,
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        })
+    }
+
+    pub fn contains(&self, now: &glib::DateTime) -> bool {
+        let day = now.day_of_week();
+        let minute = now.hour() * 60 + now.minute();
+
+        if self.start <= self.end {
+            self.days.contains(&day) && minute >= self.start && minute < self.end
+        } else {
+            // Past midnight the second half belongs to the previous day's window.
+            let previous = if day == 1 { 7 } else { day - 1 };
+            (self.days.contains(&day) && minute >= self.start)
+                || (self.days.contains(&previous) && minute < self.end)
+        }
+    }
+}
+
+fn parse_time(text: &str) -> Option<i32> {
+    let (hour, minute) = text.trim().split_once(':')?;
+    let hour: i32 = hour.parse().ok()?;
+    let minute: i32 = minute.parse().ok()?;
+    (hour < 24 && minute < 60).then_some(hour * 60 + minute)
+}
+
+// Shell style matching with `*` only, enough for patterns like "linux*".
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == name;
+    }
+
+    let mut rest = name;
+    for (index, part) in parts.iter().enumerate() {
+        if index == 0 {
+            let Some(stripped) = rest.strip_prefix(part) else {
+                return false;
+            };
+            rest = stripped;
+        } else if index == parts.len() - 1 {
+            return rest.ends_with(part);
+        } else {
+            let Some(position) = rest.find(part) else {
+                return false;
+            };
+            rest = &rest[position + part.len()..];
+        }
+    }
+    true
+}
+
+// Link of the newest Arch news item, used to tell whether there is unread news.
+pub fn latest_news() -> Option<String> {
+    let output = runner::command("curl")
+        .args(["--silent", "--fail", "--max-time", "30", NEWS_FEED])
+        .output()
+        .ok()?;
+    let feed = String::from_utf8_lossy(&output.stdout);
+    let item = &feed[feed.find("<item>")?..];
+    let start = item.find("<link>")? + "<link>".len();
+    let end = item[start..].find("</link>")? + start;
+    Some(item[start..end].trim().to_string())
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AutoUpdateReport {
+    pub success: bool,
+    pub finished: i64,
+    pub skipped: Option<String>,
+    pub packages: Vec<String>,
+    pub excluded: Vec<String>,
+}
+
+impl AutoUpdateReport {
+    // Skipped runs are a normal outcome, not a failure.
+    pub fn failed(&self) -> bool {
+        !self.success && self.skipped.is_none()
+    }
+
+    pub fn summary(&self) -> String {
+        if let Some(reason) = self.skipped.as_ref() {
+            format!("Automatic update skipped: {}", reason)
+        } else if self.success {
+            format!("Automatic update installed {} updates", self.packages.len())
+        } else {
+            "Automatic update failed, read the log".to_string()
+        }
+    }
+}
+
+// Runs one unattended upgrade once the maintenance window is open, at most
+// once per window. Returns non-zero only when the upgrade itself failed.
+pub fn run_cli() -> i32 {
+    let settings = gio::Settings::new("org.gnome.Example");
+    if !settings.boolean("auto-update") {
+        return 0;
+    }
+
+    let Some(window) = MaintenanceWindow::parse(&settings.string("maintenance-window")) else {
+        eprintln!("Automatic updates are on, but the maintenance window is invalid");
+        return 2;
+    };
+    let Ok(now) = glib::DateTime::now_local() else {
+        return 1;
+    };
+    if !window.contains(&now) {
+        return 0;
+    }
+    // A window lasts at most a day, never run twice inside one.
+    if now.to_unix() - settings.int64("auto-update-last-run") < 24 * 60 * 60 {
+        return 0;
+    }
+    if let Some(deferral) = Conditions::new(&settings).deferral(Activity::AutoUpdate) {
+        eprintln!("Automatic update deferred: {}", deferral.reason());
+        return 0;
+    }
+
+    let _ = settings.set_int64("auto-update-last-run", now.to_unix());
+    // Until the user opens the news in the app, only what comes out after
+    // the first run counts as unread.
+    if settings.string("news-last-read").is_empty() {
+        if let Some(latest) = latest_news() {
+            let _ = settings.set_string("news-last-read", &latest);
+        }
+    }
+    gio::Settings::sync();
+
+    let exclude: Vec<String> = settings.strv("auto-update-exclude").iter().map(|p| p.to_string()).collect();
+    let report = run(
+        &exclude,
+        &Holds::load(),
+        settings.boolean("auto-update-skip-on-news"),
+        &settings.string("news-last-read"),
+    );
+    println!("{}", report.summary());
+
+    if report.skipped.is_none() {
+        if let (Some(config), Some(transaction)) = (FleetConfig::load(), Report::last_transaction()) {
+            fleet::send(&config, FleetEvent::Upgrade, &transaction);
+        }
+    }
+    if report.failed() { 1 } else { 0 }
+}
+
+// Runs one unattended upgrade, holding back anything matching `exclude`.
+pub fn run(exclude: &[String], holds: &Holds, skip_on_news: bool, last_read_news: &str) -> AutoUpdateReport {
+    let mut report = AutoUpdateReport::default();
+
+    if skip_on_news {
+        if let Some(latest) = latest_news() {
+            if latest != last_read_news {
+                report.skipped = Some("there is unread Arch news".to_string());
+                report.finished = glib::real_time() / 1_000_000;
+                write_report(&report);
+                return report;
+            }
+        }
+    }
+
+    let pending = match pacman::checkupdates() {
+        Ok(pending) => pending,
+        Err(e) => {
+            eprintln!("Failed to check for updates: {}", e);
+            report.finished = glib::real_time() / 1_000_000;
+            write_report(&report);
+            return report;
+        }
+    };
+    for update in pending {
+        if exclude.iter().any(|pattern| matches_pattern(pattern, &update.name))
+            || holds.is_held(&update.name, &update.new_version)
+        {
+            report.excluded.push(update.name);
+        } else {
+            report.packages.push(update.name);
+        }
+    }
+
+    if report.packages.is_empty() {
+        report.success = true;
+        report.skipped = Some("nothing to update".to_string());
+    } else {
+        // Never prompts, without the polkit rule pkexec simply fails.
+        let mut command = runner::command("pkexec");
+        command.arg("--disable-internal-agent");
+        command.arg(std::env::current_exe().unwrap_or_else(|_| "updater-new".into()));
+        command.arg(HELPER_ARG);
+        if report.packages.iter().any(|name| name == keyring::KEYRING_PACKAGE) {
+            command.arg("--keyring-first");
+        }
+        command.args(holds::ignore_args(&report.excluded));
+
+        report.success = match command.output() {
+            Ok(output) => {
+                if !output.status.success() {
+                    eprintln!("Automatic update failed with exit code: {}", output.status);
+                    eprintln!("Stderr: {}", String::from_utf8_lossy(&output.stderr));
+                }
+                output.status.success()
+            }
+            Err(e) => {
+                eprintln!("Failed to even launch pkexec: {}", e);
+                false
+            }
+        };
+    }
+
+    report.finished = glib::real_time() / 1_000_000;
+    write_report(&report);
+    report
+}
+
+// Runs as root. Only ever upgrades the whole system from the configured
+// repositories, the arguments can hold packages back but not add any.
+pub fn run_helper(args: &[String]) -> i32 {
+    let mut keyring_first = false;
+    let mut ignored: Vec<String> = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--keyring-first" => keyring_first = true,
+            "--ignore" => ignored.extend(args.next().into_iter().flat_map(|list| list.split(',')).map(str::to_string)),
+            _ => {
+                eprintln!("{} does not take {}", HELPER_ARG, arg);
+                return 2;
+            }
+        }
+    }
+    if let Some(name) = ignored.iter().find(|name| !is_package_name(name)) {
+        eprintln!("Not a package name: {}", name);
+        return 2;
+    }
+
+    if keyring_first && !ignored.iter().any(|name| name == keyring::KEYRING_PACKAGE) {
+        let status = Command::new("pacman")
+            .args(["-Sy", "--needed", "--noconfirm", keyring::KEYRING_PACKAGE])
+            .args(holds::ignore_args(&ignored))
+            .status();
+        if !status.is_ok_and(|status| status.success()) {
+            eprintln!("Updating {} failed", keyring::KEYRING_PACKAGE);
+            return 1;
+        }
+    }
+
+    let mut command = Command::new("pacman");
+    command.args(["-Syu", "--noconfirm"]);
+    command.args(holds::ignore_args(&ignored));
+    match command.status() {
+        Ok(status) if status.success() => 0,
+        Ok(status) => {
+            eprintln!("pacman failed with exit code: {}", status);
+            1
+        }
+        Err(e) => {
+            eprintln!("Failed to launch pacman: {}", e);
+            1
+        }
+    }
+}
+
+// What makepkg allows in pkgname, so nothing can pass as an option.
+fn is_package_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(['-', '.'])
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || "@._+-".contains(c))
+}
+
+fn report_path() -> PathBuf {
+    glib::user_state_dir().join("updater-new").join("auto-update-report")
+}
+
+fn write_report(report: &AutoUpdateReport) {
+    let path = report_path();
+    if let Some(parent) = path.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+
+    let contents = format!(
+        "status={}\nfinished={}\nskipped={}\npackages={}\nexcluded={}\n",
+        if report.success { "ok" } else { "failed" },
+        report.finished,
+        report.skipped.as_deref().unwrap_or(""),
+        report.packages.join(" "),
+        report.excluded.join(" "),
+    );
+    if let Err(e) = fs::write(&path, contents) {
+        eprintln!("Failed to write {}: {}", path.display(), e);
+    }
+}
+
+pub fn read_report() -> Option<AutoUpdateReport> {
+    let contents = fs::read_to_string(report_path()).ok()?;
+    let mut report = AutoUpdateReport::default();
+    for line in contents.lines() {
+        match line.split_once('=') {
+            Some(("status", value)) => report.success = value == "ok",
+            Some(("finished", value)) => report.finished = value.parse().ok()?,
+            Some(("skipped", value)) if !value.is_empty() => report.skipped = Some(value.to_string()),
+            Some(("packages", value)) => report.packages = value.split_whitespace().map(str::to_string).collect(),
+            Some(("excluded", value)) => report.excluded = value.split_whitespace().map(str::to_string).collect(),
+            _ => {}
+        }
+    }
+    (report.finished > 0).then_some(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-10-20 was a Sunday.
+    fn at(day: i32, hour: i32, minute: i32) -> glib::DateTime {
+        glib::DateTime::from_utc(2024, 10, day, hour, minute, 0.0).unwrap()
+    }
+
+    #[test]
+    fn parses_days_and_times() {
+        assert_eq!(
+            MaintenanceWindow::parse("Sun 02:00-04:00"),
+            Some(MaintenanceWindow { days: vec![7], start: 120, end: 240 }),
+        );
+        assert_eq!(
+            MaintenanceWindow::parse("Mon,thursday 22:00-01:00"),
+            Some(MaintenanceWindow { days: vec![1, 4], start: 22 * 60, end: 60 }),
+        );
+        assert_eq!(MaintenanceWindow::parse(" Daily 03:00-05:30 ").map(|window| window.days), Some((1..=7).collect()));
+    }
+
+    #[test]
+    fn rejects_malformed_windows() {
+        for text in ["", "Sun", "Sun 02:00", "Funday 02:00-04:00", "Sun 24:00-01:00", "Sun 02:60-03:00", "Sun 2-4"] {
+            assert_eq!(MaintenanceWindow::parse(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn contains_within_a_day() {
+        let window = MaintenanceWindow::parse("Sun 02:00-04:00").unwrap();
+        assert!(window.contains(&at(20, 2, 0)));
+        assert!(window.contains(&at(20, 3, 59)));
+        assert!(!window.contains(&at(20, 1, 59)));
+        assert!(!window.contains(&at(20, 4, 0)));
+        assert!(!window.contains(&at(19, 3, 0)));
+    }
+
+    #[test]
+    fn contains_past_midnight() {
+        let window = MaintenanceWindow::parse("Sun 22:00-01:00").unwrap();
+        assert!(window.contains(&at(20, 23, 30)));
+        // Monday morning still belongs to Sunday's window.
+        assert!(window.contains(&at(21, 0, 30)));
+        assert!(!window.contains(&at(21, 1, 0)));
+        assert!(!window.contains(&at(20, 0, 30)));
+        assert!(!window.contains(&at(21, 23, 30)));
+    }
+
+    #[test]
+    fn matches_patterns() {
+        assert!(matches_pattern("linux", "linux"));
+        assert!(!matches_pattern("linux", "linux-lts"));
+        assert!(matches_pattern("linux*", "linux"));
+        assert!(matches_pattern("linux*", "linux-lts"));
+        assert!(matches_pattern("*-dkms", "nvidia-dkms"));
+        assert!(!matches_pattern("*-dkms", "nvidia-utils"));
+        assert!(matches_pattern("lib32-*-utils", "lib32-nvidia-utils"));
+        assert!(!matches_pattern("nvidia*", "lib32-nvidia-utils"));
+        assert!(matches_pattern("*", "anything"));
+    }
+}
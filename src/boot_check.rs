@@ -0,0 +1,470 @@
+use gtk::glib;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::pacman;
+use crate::runner;
+
+const BOOT: &str = "/boot";
+// Where the ESP or the XBOOTLDR partition is usually mounted.
+const LOADER_ROOTS: [&str; 3] = ["/efi", "/boot", "/boot/efi"];
+const GRUB_CFG: &str = "/boot/grub/grub.cfg";
+
+// Something about the boot setup that looks broken after an upgrade.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootIssue {
+    pub title: String,
+    pub detail: String,
+    // The next boot likely fails, restarting asks first.
+    pub blocking: bool,
+}
+
+impl BootIssue {
+    fn blocking(title: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self { title: title.into(), detail: detail.into(), blocking: true }
+    }
+
+    fn advice(title: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self { title: title.into(), detail: detail.into(), blocking: false }
+    }
+}
+
+// The post-update summary, `verified` lists what was checked and found fine.
+#[derive(Debug, Clone, Default)]
+pub struct BootCheck {
+    pub issues: Vec<BootIssue>,
+    pub verified: Vec<String>,
+}
+
+impl BootCheck {
+    pub fn blocks_reboot(&self) -> bool {
+        self.issues.iter().any(|issue| issue.blocking)
+    }
+}
+
+// An installed kernel, from /usr/lib/modules/<release>/pkgbase.
+#[derive(Debug, Clone)]
+struct Kernel {
+    pkgbase: String,
+    release: String,
+}
+
+fn installed_kernels() -> Vec<Kernel> {
+    let Ok(entries) = fs::read_dir(runner::modules_dir()) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let pkgbase = fs::read_to_string(entry.path().join("pkgbase")).ok()?;
+            Some(Kernel {
+                pkgbase: pkgbase.trim().to_string(),
+                release: entry.file_name().to_string_lossy().to_string(),
+            })
+        })
+        .collect()
+}
+
+// Runs after a successful transaction that touched anything
+// pacman::is_boot_critical matches. `output` is what pacman and its hooks
+// printed, `started` when the transaction began.
+pub fn check_after_upgrade(upgraded: &[String], output: &str, started: SystemTime) -> BootCheck {
+    let kernels = installed_kernels();
+    let upgraded_kernels: Vec<&Kernel> = kernels
+        .iter()
+        .filter(|kernel| upgraded.contains(&kernel.pkgbase))
+        .collect();
+    let mkinitcpio = upgraded.iter().any(|name| name.starts_with("mkinitcpio"));
+    let dkms_module = upgraded.iter().any(|name| name.ends_with("-dkms"));
+
+    let mut check = BootCheck::default();
+
+    // A new mkinitcpio rebuilds every image, a new kernel only its own.
+    let rebuilt: Vec<&Kernel> = if mkinitcpio { kernels.iter().collect() } else { upgraded_kernels.clone() };
+    for kernel in rebuilt {
+        match check_initramfs(kernel, output, started) {
+            Some(issue) => check.issues.push(issue),
+            None => check.verified.push(format!("The initramfs of {} was regenerated", kernel.pkgbase)),
+        }
+    }
+
+    // A new module is built for every kernel, a new kernel needs every module.
+    let built_for: Vec<&Kernel> = if dkms_module { kernels.iter().collect() } else { upgraded_kernels.clone() };
+    if !built_for.is_empty() {
+        check.issues.extend(check_dkms(&built_for, output));
+    }
+
+    // Entries only go stale when kernels, images or the loader change.
+    let bootloader = upgraded.iter().any(|name| matches!(name.as_str(), "grub" | "systemd" | "systemd-boot"));
+    if mkinitcpio || bootloader || !upgraded_kernels.is_empty() {
+        check_systemd_boot(&kernels, &mut check);
+        check_grub(&kernels, upgraded.iter().any(|name| name == "grub"), &mut check);
+    }
+
+    check
+}
+
+// Trusts mkinitcpio's own verdict when its hook output is there, the image
+// timestamps otherwise (dracut and kernel-install print differently).
+fn check_initramfs(kernel: &Kernel, output: &str, started: SystemTime) -> Option<BootIssue> {
+    match mkinitcpio_result(output, &kernel.pkgbase) {
+        Some(false) => {
+            return Some(BootIssue::blocking(
+                format!("The initramfs of {} failed to build", kernel.pkgbase),
+                format!("mkinitcpio reported an error, run \"mkinitcpio -p {}\" and fix what it reports before restarting.", kernel.pkgbase)));
+        }
+        Some(true) => return None,
+        None if image_rebuilt(&kernel.pkgbase, started) => return None,
+        None => {}
+    }
+    Some(BootIssue::blocking(
+        format!("The initramfs of {} was not regenerated", kernel.pkgbase),
+        format!("No new initramfs for {} showed up in {}. Regenerate it with \"mkinitcpio -p {}\" or your initramfs generator.",
+            kernel.release, BOOT, kernel.pkgbase)))
+}
+
+// Whether the presets of `pkgbase` built fine according to the hook output,
+// None when mkinitcpio didn't build them. An error fails the preset even when
+// mkinitcpio still wrote an image.
+fn mkinitcpio_result(output: &str, pkgbase: &str) -> Option<bool> {
+    let preset = format!("/etc/mkinitcpio.d/{}.preset", pkgbase);
+    let mut result = None;
+    let mut in_preset = false;
+    for line in output.lines() {
+        let line = line.trim();
+        if let Some(rest) = line.strip_prefix("==> Building image from preset: ") {
+            in_preset = rest.starts_with(&preset);
+        } else if in_preset && (line.contains("Image generation FAILED") || line.starts_with("==> ERROR:")) {
+            result = Some(false);
+        } else if in_preset && line.contains("Image generation successful") && result.is_none() {
+            result = Some(true);
+        }
+    }
+    result
+}
+
+fn image_rebuilt(pkgbase: &str, started: SystemTime) -> bool {
+    let image = Path::new(BOOT).join(format!("initramfs-{}.img", pkgbase));
+    fs::metadata(image)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| modified >= started)
+}
+
+fn check_dkms(kernels: &[&Kernel], output: &str) -> Vec<BootIssue> {
+    if glib::find_program_in_path("dkms").is_none() {
+        return Vec::new();
+    }
+    let status = match runner::command("dkms").arg("status").env("LC_ALL", "C").output() {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).to_string(),
+        Err(e) => {
+            eprintln!("Failed to run dkms status: {}", e);
+            return Vec::new();
+        }
+    };
+
+    // DKMS only builds for kernels with headers, /usr/lib/modules/<release>/build
+    // comes with them. Without, a missing module is expected, not a failure.
+    let (kernels, headerless): (Vec<&Kernel>, Vec<&Kernel>) = kernels
+        .iter()
+        .copied()
+        .partition(|kernel| runner::modules_dir().join(&kernel.release).join("build").exists());
+
+    let mut issues = Vec::new();
+    let modules = dkms_modules(&status);
+    for module in modules.iter() {
+        let missing: Vec<&str> = kernels
+            .iter()
+            .filter(|kernel| !dkms_installed(&status, module, &kernel.release))
+            .map(|kernel| kernel.release.as_str())
+            .collect();
+        if missing.is_empty() {
+            continue;
+        }
+        let hook_error = output
+            .lines()
+            .find(|line| line.contains(module.as_str()) && (line.contains("Error!") || line.contains("failed")))
+            .map(|line| format!("\n\nThe hook reported: {}", line.trim()))
+            .unwrap_or_default();
+        issues.push(BootIssue::blocking(
+            format!("The {} module was not built", module),
+            format!("DKMS has no {} module for {}. The graphics or other hardware it drives may not work \
+                     after restarting, run \"dkms autoinstall -k {}\" to see why.{}",
+                module, missing.join(", "), missing[0], hook_error)));
+    }
+    for kernel in headerless {
+        let missing: Vec<&str> = modules
+            .iter()
+            .filter(|module| !dkms_installed(&status, module, &kernel.release))
+            .map(String::as_str)
+            .collect();
+        if missing.is_empty() {
+            continue;
+        }
+        issues.push(BootIssue::advice(
+            format!("No headers for {}", kernel.pkgbase),
+            format!("DKMS can't build {} for {} without the {}-headers package. Install it if you boot \
+                     this kernel, otherwise nothing needs to be done.",
+                missing.join(", "), kernel.release, kernel.pkgbase)));
+    }
+    issues
+}
+
+// "nvidia/550.67, 6.8.2-arch1-1, x86_64: installed", older DKMS separates
+// the version with a comma too.
+fn dkms_modules(status: &str) -> Vec<String> {
+    let mut modules: Vec<String> = Vec::new();
+    for line in status.lines() {
+        let Some(name) = line.split(['/', ',']).next().map(str::trim).filter(|name| !name.is_empty()) else {
+            continue;
+        };
+        if !modules.iter().any(|module| module == name) {
+            modules.push(name.to_string());
+        }
+    }
+    modules
+}
+
+fn dkms_installed(status: &str, module: &str, release: &str) -> bool {
+    status.lines().any(|line| {
+        let Some((fields, state)) = line.split_once(": ") else {
+            return false;
+        };
+        let mut fields = fields.split(['/', ',']).map(str::trim);
+        fields.next() == Some(module)
+            && fields.any(|field| field == release)
+            && state.trim_start().starts_with("installed")
+    })
+}
+
+// Type #1 entries of systemd-boot, on the ESP or the XBOOTLDR partition.
+// Unified kernel images in EFI/Linux carry their own initramfs and are only
+// looked at to tell whether a kernel has an entry at all.
+fn check_systemd_boot(kernels: &[Kernel], check: &mut BootCheck) {
+    let mut found = false;
+    let mut unreadable = false;
+    let mut entries = 0;
+    let mut images = 0;
+    let mut booted: Vec<String> = Vec::new();
+
+    for root in LOADER_ROOTS.iter().map(Path::new) {
+        let dir = root.join("loader").join("entries");
+        let files = match fs::read_dir(&dir) {
+            Ok(files) => files,
+            Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+                found = true;
+                unreadable = true;
+                check.issues.push(BootIssue::advice(
+                    format!("The boot entries in {} could not be read", root.display()),
+                    "Only root can read them here, so they were not verified. \
+                     Check them with \"bootctl list\" before restarting."));
+                continue;
+            }
+            Err(_) => continue,
+        };
+        found = true;
+
+        for path in files.flatten().map(|entry| entry.path()) {
+            if path.extension().is_none_or(|extension| extension != "conf") {
+                continue;
+            }
+            let Ok(contents) = fs::read_to_string(&path) else {
+                continue;
+            };
+            entries += 1;
+            let name = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+            for (key, value) in contents.lines().filter_map(|line| line.trim().split_once(char::is_whitespace)) {
+                if !matches!(key, "linux" | "initrd" | "efi" | "uki") {
+                    continue;
+                }
+                let file = value.trim();
+                if !root.join(file.trim_start_matches('/')).is_file() {
+                    check.issues.push(BootIssue::blocking(
+                        format!("The boot entry {} points to a missing file", name),
+                        format!("{} has \"{} {}\", but there is no such file in {}. \
+                                 Restarting into this entry will fail.", path.display(), key, file, root.display())));
+                } else if key == "linux" {
+                    booted.push(file_name(file));
+                }
+            }
+        }
+
+        if let Ok(files) = fs::read_dir(root.join("EFI").join("Linux")) {
+            let before = booted.len();
+            booted.extend(files.flatten().map(|image| image.file_name().to_string_lossy().to_string()));
+            images += booted.len() - before;
+        }
+    }
+    if !found {
+        return;
+    }
+    // Nothing was read, so nothing was verified either.
+    if entries == 0 && images == 0 {
+        if !unreadable {
+            check.issues.push(BootIssue::advice(
+                "The systemd-boot entries could not be verified",
+                "No readable entry or unified kernel image was found on the ESP or the XBOOTLDR partition. \
+                 Check them with \"bootctl list\" before restarting."));
+        }
+        return;
+    }
+
+    for kernel in kernels_with_image(kernels) {
+        let image = format!("vmlinuz-{}", kernel.pkgbase);
+        if !booted.iter().any(|file| *file == image || file.contains(&kernel.pkgbase)) {
+            check.issues.push(BootIssue::advice(
+                format!("systemd-boot has no entry for {}", kernel.pkgbase),
+                format!("{} is installed, but no boot entry or unified kernel image uses it. \
+                         Add an entry to loader/entries if you want to boot it.", kernel.pkgbase)));
+        }
+    }
+    check.verified.push(if images == 0 {
+        format!("systemd-boot: {} entries checked", entries)
+    } else {
+        format!("systemd-boot: {} entries and {} unified kernel images checked", entries, images)
+    });
+}
+
+fn check_grub(kernels: &[Kernel], grub_upgraded: bool, check: &mut BootCheck) {
+    if grub_upgraded {
+        // pacman only replaces the files in /usr/lib/grub, never the copy
+        // in the boot sector or on the ESP.
+        check.issues.push(BootIssue::advice(
+            "GRUB was updated",
+            "The installed boot loader is not replaced by the package. If the release notes ask for it, \
+             run grub-install with the options you originally used, followed by \
+             \"grub-mkconfig -o /boot/grub/grub.cfg\"."));
+    }
+
+    let config = match fs::read_to_string(GRUB_CFG) {
+        Ok(config) => config,
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+            check.issues.push(BootIssue::advice(
+                format!("{} could not be read", GRUB_CFG),
+                "Only root can read it here, so its entries were not verified."));
+            return;
+        }
+        Err(_) => return,
+    };
+
+    let mut referenced: Vec<String> = Vec::new();
+    for line in config.lines() {
+        let mut words = line.split_whitespace();
+        let Some(key) = words.next() else {
+            continue;
+        };
+        if !matches!(key, "linux" | "linuxefi" | "initrd" | "initrdefi") {
+            continue;
+        }
+        let files: Vec<&str> = if key.starts_with("initrd") { words.collect() } else { words.take(1).collect() };
+        // Paths built from GRUB variables can't be resolved from here.
+        for file in files.into_iter().filter(|file| !file.contains('$')) {
+            if !grub_file(file).is_file() {
+                check.issues.push(BootIssue::blocking(
+                    format!("{} points to a missing file", GRUB_CFG),
+                    format!("It has \"{} {}\", but the file is not in {}. Regenerate it with \
+                             \"grub-mkconfig -o {}\" before restarting.", key, file, BOOT, GRUB_CFG)));
+            } else if key.starts_with("linux") {
+                referenced.push(file_name(file));
+            }
+        }
+    }
+
+    let mut listed = 0;
+    for kernel in kernels_with_image(kernels) {
+        if referenced.contains(&format!("vmlinuz-{}", kernel.pkgbase)) {
+            listed += 1;
+        } else {
+            check.issues.push(BootIssue::advice(
+                format!("{} has no entry for {}", GRUB_CFG, kernel.pkgbase),
+                format!("{} is installed, but the GRUB menu doesn't list it. \
+                         Run \"grub-mkconfig -o {}\" to add it.", kernel.pkgbase, GRUB_CFG)));
+        }
+    }
+    check.verified.push(format!("GRUB: {} installed kernels are in grub.cfg", listed));
+}
+
+// GRUB paths are relative to the partition holding them, which is /boot
+// itself when that is mounted separately.
+fn grub_file(file: &str) -> PathBuf {
+    let path = Path::new(file);
+    if path.starts_with(BOOT) && path.is_file() {
+        return path.to_path_buf();
+    }
+    Path::new(BOOT).join(file.trim_start_matches('/'))
+}
+
+fn file_name(path: &str) -> String {
+    path.rsplit('/').next().unwrap_or(path).to_string()
+}
+
+// Kernels whose image mkinitcpio's hook placed in /boot, the ones a boot
+// loader entry can point at.
+fn kernels_with_image(kernels: &[Kernel]) -> impl Iterator<Item = &Kernel> {
+    kernels
+        .iter()
+        .filter(|kernel| Path::new(BOOT).join(format!("vmlinuz-{}", kernel.pkgbase)).is_file())
+}
+
+pub fn describe(check: &BootCheck) -> String {
+    check.issues
+        .iter()
+        .map(|issue| format!("{}\n{}", issue.title, issue.detail))
+        .chain(check.verified.iter().map(|line| format!("Verified: {}", line)))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+// Whether the packages touch anything check_after_upgrade looks at.
+pub fn needs_check(upgraded: &[String]) -> bool {
+    upgraded.iter().any(|name| pacman::is_boot_critical(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Recorded with `LC_ALL=C pacman -Syu` and `dkms status`, see tests/boot-check.
+    fn sample(name: &str) -> String {
+        let path = format!("{}/tests/boot-check/{}", env!("CARGO_MANIFEST_DIR"), name);
+        fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path, e))
+    }
+
+    #[test]
+    fn mkinitcpio_built_every_preset() {
+        assert_eq!(mkinitcpio_result(&sample("mkinitcpio-success.out"), "linux"), Some(true));
+    }
+
+    #[test]
+    fn mkinitcpio_error_fails_the_preset() {
+        let output = sample("mkinitcpio-success.out");
+        // The image was still written, without the nvidia module.
+        assert_eq!(mkinitcpio_result(&output, "linux-lts"), Some(false));
+        assert_eq!(mkinitcpio_result(&sample("mkinitcpio-failed.out"), "linux"), Some(false));
+    }
+
+    #[test]
+    fn mkinitcpio_did_not_build() {
+        assert_eq!(mkinitcpio_result(&sample("mkinitcpio-success.out"), "linux-zen"), None);
+        assert_eq!(mkinitcpio_result("", "linux"), None);
+    }
+
+    #[test]
+    fn dkms_lists_each_module_once() {
+        assert_eq!(dkms_modules(&sample("dkms-status.out")), ["nvidia", "v4l2loopback", "broadcom-wl"]);
+    }
+
+    #[test]
+    fn dkms_installed_per_kernel() {
+        let status = sample("dkms-status.out");
+        assert!(dkms_installed(&status, "nvidia", "6.11.5-arch1-1"));
+        // Built but not installed.
+        assert!(!dkms_installed(&status, "nvidia", "6.6.58-1-lts"));
+        // The older format with a comma before the version.
+        assert!(dkms_installed(&status, "v4l2loopback", "6.11.5-arch1-1"));
+        assert!(dkms_installed(&status, "v4l2loopback", "6.6.58-1-lts"));
+        assert!(!dkms_installed(&status, "broadcom-wl", "6.11.5-arch1-1"));
+        assert!(!dkms_installed(&status, "zfs", "6.11.5-arch1-1"));
+    }
+}
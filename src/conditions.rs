@@ -0,0 +1,98 @@
+use gtk::gio;
+use gtk::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activity {
+    Check,
+    Download,
+    AutoUpdate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Deferral {
+    Offline,
+    Metered,
+    OnBattery,
+}
+
+impl Deferral {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Deferral::Offline => "no network connection",
+            Deferral::Metered => "the connection is metered",
+            Deferral::OnBattery => "running on battery",
+        }
+    }
+}
+
+// Decides whether background work may run right now, based on the network
+// state and whether the machine runs on battery.
+#[derive(Debug)]
+pub struct Conditions {
+    network: gio::NetworkMonitor,
+    upower: Option<gio::DBusProxy>,
+    settings: gio::Settings,
+}
+
+impl Conditions {
+    pub fn new(settings: &gio::Settings) -> Self {
+        Self {
+            network: gio::NetworkMonitor::default(),
+            upower: upower_proxy(),
+            settings: settings.clone(),
+        }
+    }
+
+    pub fn on_battery(&self) -> bool {
+        self.upower
+            .as_ref()
+            .and_then(|proxy| proxy.cached_property("OnBattery"))
+            .and_then(|value| value.get::<bool>())
+            .unwrap_or(false)
+    }
+
+    pub fn deferral(&self, activity: Activity) -> Option<Deferral> {
+        if !self.network.is_network_available() {
+            return Some(Deferral::Offline);
+        }
+
+        // Checking only fetches the small sync databases.
+        if activity == Activity::Check {
+            return None;
+        }
+
+        if self.network.is_network_metered() && !self.settings.boolean("allow-metered") {
+            return Some(Deferral::Metered);
+        }
+        if self.on_battery() && !self.settings.boolean("allow-on-battery") {
+            return Some(Deferral::OnBattery);
+        }
+        None
+    }
+}
+
+// UPower normally lives on the system bus. Setting UPDATER_UPOWER_BUS=session
+// talks to a stand-in on the session bus instead, for example
+// `python3 -m dbusmock --template upower`.
+fn upower_proxy() -> Option<gio::DBusProxy> {
+    let bus = match std::env::var("UPDATER_UPOWER_BUS").as_deref() {
+        Ok("session") => gio::BusType::Session,
+        _ => gio::BusType::System,
+    };
+
+    match gio::DBusProxy::for_bus_sync(
+        bus,
+        gio::DBusProxyFlags::DO_NOT_AUTO_START,
+        None,
+        "org.freedesktop.UPower",
+        "/org/freedesktop/UPower",
+        "org.freedesktop.UPower",
+        gio::Cancellable::NONE,
+    ) {
+        Ok(proxy) => Some(proxy),
+        Err(e) => {
+            eprintln!("UPower is not available: {}", e);
+            None
+        }
+    }
+}
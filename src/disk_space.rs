@@ -0,0 +1,149 @@
+use gtk::gio;
+use gtk::gio::prelude::*;
+
+use crate::pacman::{self, PendingUpdate};
+use crate::pacman_conf::PacmanConf;
+use crate::prefetch;
+use crate::runner;
+
+const ROOT: &str = "/";
+const BOOT: &str = "/boot";
+// Kept free on top of what the update itself needs, hooks and scriptlets
+// write temporary files too.
+const MARGIN: u64 = 100 * 1024 * 1024;
+// Room for a new kernel image and initramfs when the current ones can't be read.
+const KERNEL_ESTIMATE: u64 = 128 * 1024 * 1024;
+
+// A file system that would run out of space, with the paths that live on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shortage {
+    pub paths: Vec<String>,
+    pub needed: u64,
+    pub free: u64,
+}
+
+impl Shortage {
+    pub fn describe(&self) -> String {
+        format!("{} needs {}, only {} is free",
+            self.paths.join(" and "),
+            pacman::format_size(self.needed),
+            pacman::format_size(self.free))
+    }
+}
+
+// Downloads land in the cache directory, the installed size difference on
+// the root and new kernel images in /boot. Paths on the same file system are
+// added up, as they usually are on a single partition install.
+pub fn check(updates: &[PendingUpdate]) -> Vec<Shortage> {
+    let cache_dir = PacmanConf::load()
+        .ok()
+        .and_then(|conf| conf.option("CacheDir"))
+        .unwrap_or_else(|| prefetch::SYSTEM_CACHE.to_string());
+
+    let download: u64 = updates.iter().map(|update| update.download_size).sum();
+    let installed: i64 = updates.iter().map(|update| update.installed_delta).sum();
+    let kernels = installed_kernels();
+    let boot: u64 = updates
+        .iter()
+        .filter(|update| kernels.contains(&update.name))
+        .map(|update| kernel_images_size(&update.name))
+        .sum();
+
+    let mut requirements = vec![(cache_dir, download), (ROOT.to_string(), installed.max(0) as u64)];
+    if boot > 0 {
+        requirements.push((BOOT.to_string(), boot));
+    }
+
+    // (file system id, shortage so far)
+    let mut filesystems: Vec<(String, Shortage)> = Vec::new();
+    for (path, needed) in requirements {
+        let Some((id, free)) = filesystem(&path) else {
+            continue;
+        };
+        match filesystems.iter_mut().find(|(known, _)| *known == id) {
+            Some((_, shortage)) => {
+                shortage.paths.push(path);
+                shortage.needed += needed;
+            }
+            None => filesystems.push((id, Shortage { paths: vec![path], needed: needed + MARGIN, free })),
+        }
+    }
+
+    filesystems
+        .into_iter()
+        .map(|(_, shortage)| shortage)
+        .filter(|shortage| shortage.needed > shortage.free)
+        .collect()
+}
+
+fn filesystem(path: &str) -> Option<(String, u64)> {
+    let file = gio::File::for_path(path);
+    let id = file
+        .query_info("id::filesystem", gio::FileQueryInfoFlags::NONE, gio::Cancellable::NONE)
+        .ok()?
+        .attribute_string("id::filesystem")?
+        .to_string();
+    let free = file
+        .query_filesystem_info("filesystem::free", gio::Cancellable::NONE)
+        .ok()?
+        .attribute_uint64("filesystem::free");
+    Some((id, free))
+}
+
+// Installed kernels leave their package name in /usr/lib/modules/*/pkgbase.
+fn installed_kernels() -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(runner::modules_dir()) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| std::fs::read_to_string(entry.path().join("pkgbase")).ok())
+        .map(|pkgbase| pkgbase.trim().to_string())
+        .collect()
+}
+
+// mkinitcpio writes /boot/vmlinuz-<pkgbase> and its initramfs images, the new
+// ones are about as large as the current ones.
+fn kernel_images_size(name: &str) -> u64 {
+    let prefixes = [format!("vmlinuz-{}", name), format!("initramfs-{}", name)];
+    let size: u64 = std::fs::read_dir(BOOT)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            prefixes.iter().any(|prefix| {
+                file_name == *prefix || file_name == format!("{}.img", prefix) || file_name.starts_with(&format!("{}-", prefix))
+            })
+        })
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum();
+
+    if size > 0 { size } else { KERNEL_ESTIMATE }
+}
+
+// Keeps the newest cached version of each installed package and drops the
+// cache of uninstalled ones. Falls back to pacman when pacman-contrib is missing.
+pub fn clean_cache() -> bool {
+    let mut command = runner::command("pkexec");
+    if gtk::glib::find_program_in_path("paccache").is_some() {
+        command.args(["sh", "-c", "paccache -r -k 1 && paccache -r -u -k 0"]);
+    } else {
+        command.args(["pacman", "-Sc", "--noconfirm"]);
+    }
+
+    match command.output() {
+        Ok(output) => {
+            if !output.status.success() {
+                eprintln!("Cleaning the cache failed with exit code: {}", output.status);
+                eprintln!("Stderr: {}", String::from_utf8_lossy(&output.stderr));
+            }
+            output.status.success()
+        }
+        Err(e) => {
+            eprintln!("Failed to even launch pkexec: {}", e);
+            false
+        }
+    }
+}
@@ -0,0 +1,359 @@
+use crate::keyring;
+use crate::runner;
+
+pub const LOCK_FILE: &str = "/var/lib/pacman/db.lck";
+
+// Why a pacman transaction failed, read from what it printed on stderr.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PacmanError {
+    // Files that another package, or nothing at all, already owns.
+    ConflictingFiles(Vec<String>),
+    UnresolvableDependencies(Vec<String>),
+    // Pairs of packages that can't be installed together.
+    PackageConflict(Vec<String>),
+    DiskSpace,
+    Lock,
+    Network,
+    Signature,
+    Interrupted,
+    // The password prompt was dismissed.
+    Cancelled,
+    Unknown(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorAction {
+    Retry,
+    RepairKeyring,
+    RemoveLock,
+    EditRepositories,
+    CleanCache,
+}
+
+impl ErrorAction {
+    pub fn id(&self) -> &'static str {
+        match self {
+            ErrorAction::Retry => "retry",
+            ErrorAction::RepairKeyring => "repair-keyring",
+            ErrorAction::RemoveLock => "remove-lock",
+            ErrorAction::EditRepositories => "repos",
+            ErrorAction::CleanCache => "clean-cache",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ErrorAction::Retry => "Try Again",
+            ErrorAction::RepairKeyring => "Repair Keyring",
+            ErrorAction::RemoveLock => "Remove Lock",
+            ErrorAction::EditRepositories => "Repositories",
+            ErrorAction::CleanCache => "Clean Cache",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        [ErrorAction::Retry, ErrorAction::RepairKeyring, ErrorAction::RemoveLock, ErrorAction::EditRepositories, ErrorAction::CleanCache]
+            .into_iter()
+            .find(|action| action.id() == id)
+    }
+}
+
+impl PacmanError {
+    pub fn classify(output: &str) -> Self {
+        let conflicting_files = collect(output, |line| {
+            // "pkg: /path exists in filesystem", newer pacman adds "(owned by other)".
+            let (file, _) = line.split_once(" exists in filesystem")?;
+            Some(file.split_once(": ").map(|(_, path)| path).unwrap_or(file).to_string())
+        });
+        // The libalpm backend only knows that there were some.
+        if !conflicting_files.is_empty() || output.contains("(conflicting files)") {
+            return PacmanError::ConflictingFiles(conflicting_files);
+        }
+
+        let conflicts = collect(output, |line| {
+            let (pair, _) = line.split_once(" are in conflict")?;
+            // The question asked before --noconfirm declines it names the same pair.
+            (!line.contains("Remove ")).then(|| pair.to_string())
+        });
+        if !conflicts.is_empty() || output.contains("unresolvable package conflicts detected") {
+            return PacmanError::PackageConflict(conflicts);
+        }
+
+        let dependencies = collect(output, |line| {
+            if let Some((_, rest)) = line.split_once("unable to satisfy dependency ")
+                .or_else(|| line.split_once("breaks dependency "))
+            {
+                Some(rest.replace('\'', ""))
+            } else {
+                line.split_once("target not found: ").map(|(_, target)| format!("{} (not found)", target.trim()))
+            }
+        });
+        if !dependencies.is_empty() {
+            return PacmanError::UnresolvableDependencies(dependencies);
+        }
+
+        if keyring::is_signature_error(output) {
+            return PacmanError::Signature;
+        }
+        if output.contains("too full") || output.contains("not enough free disk space") {
+            return PacmanError::DiskSpace;
+        }
+        if output.contains("unable to lock database") {
+            return PacmanError::Lock;
+        }
+        if ["failed retrieving file", "failed to synchronize", "Could not resolve host",
+            "Operation too slow", "Connection timed out", "download library error"]
+            .iter()
+            .any(|error| output.contains(error))
+        {
+            return PacmanError::Network;
+        }
+        if ["Interrupt signal received", "transaction aborted", "could not find or read package",
+            "is missing from the database", "duplicated database entry"]
+            .iter()
+            .any(|error| output.contains(error))
+        {
+            return PacmanError::Interrupted;
+        }
+        if ["Request dismissed", "Not authorized", "Error executing command as another user"]
+            .iter()
+            .any(|error| output.contains(error))
+        {
+            return PacmanError::Cancelled;
+        }
+
+        let last_error = output
+            .lines()
+            .rev()
+            .find_map(|line| line.trim().strip_prefix("error: "))
+            .unwrap_or("")
+            .to_string();
+        PacmanError::Unknown(last_error)
+    }
+
+    pub fn title(&self) -> String {
+        match self {
+            PacmanError::ConflictingFiles(files) if files.is_empty() => "Files are in conflict".to_string(),
+            PacmanError::ConflictingFiles(files) => format!("{} conflicting files", files.len()),
+            PacmanError::UnresolvableDependencies(_) => "Dependencies could not be resolved".to_string(),
+            PacmanError::PackageConflict(_) => "Packages are in conflict".to_string(),
+            PacmanError::DiskSpace => "Not enough disk space".to_string(),
+            PacmanError::Lock => "The package database is locked".to_string(),
+            PacmanError::Network => "Packages could not be downloaded".to_string(),
+            PacmanError::Signature => "Package signatures could not be verified".to_string(),
+            PacmanError::Interrupted => "A previous update was interrupted".to_string(),
+            PacmanError::Cancelled => "Authentication was cancelled".to_string(),
+            PacmanError::Unknown(_) => "The update failed".to_string(),
+        }
+    }
+
+    pub fn explanation(&self) -> String {
+        match self {
+            PacmanError::ConflictingFiles(files) if files.is_empty() =>
+                "Some files the update installs already exist and belong to no package or to another one. \
+                 Run \"pacman -Syu\" in a terminal to see which ones.".to_string(),
+            PacmanError::ConflictingFiles(files) => format!(
+                "Some files the update installs already exist and belong to no package or to another one:\n\n{}\n\n\
+                 Check with \"pacman -Qo\" who owns them. Files left behind by hand can be deleted, \
+                 never overwrite files owned by another package.",
+                preview(files)),
+            PacmanError::UnresolvableDependencies(dependencies) => format!(
+                "These dependencies are not available from the enabled repositories:\n\n{}\n\n\
+                 This is often a package that was removed from the repositories or a repository \
+                 that is out of sync, try again later or remove the package that needs it.",
+                preview(dependencies)),
+            PacmanError::PackageConflict(pairs) => format!(
+                "The update replaces or conflicts with installed packages{}\n\n\
+                 Removing one of them was declined, the update can only go ahead once one \
+                 of the packages is removed.",
+                if pairs.is_empty() { ".".to_string() } else { format!(":\n\n{}", preview(pairs)) }),
+            PacmanError::DiskSpace =>
+                "A partition does not have enough free space for the update. Free some space, \
+                 for example by cleaning the package cache, and try again.".to_string(),
+            PacmanError::Lock => format!(
+                "Another package manager is running, or one crashed and left {} behind. \
+                 Wait for the other one to finish. If none is running the lock can be removed.",
+                LOCK_FILE),
+            PacmanError::Network =>
+                "The mirrors could not be reached or are out of date. Check the network connection \
+                 or choose other mirrors in the repository settings.".to_string(),
+            PacmanError::Signature =>
+                "This usually means the Arch Linux keyring is out of date, repair it and try again.".to_string(),
+            PacmanError::Interrupted =>
+                "The package database does not match the installed files, most likely because an \
+                 update was stopped halfway. Run the update again, if that fails too reinstall the \
+                 affected packages from a terminal.".to_string(),
+            PacmanError::Cancelled => "Nothing was changed.".to_string(),
+            PacmanError::Unknown(error) if !error.is_empty() => format!("pacman reported: {}", error),
+            PacmanError::Unknown(_) => "pacman did not say why, the details are in the log.".to_string(),
+        }
+    }
+
+    pub fn actions(&self) -> Vec<ErrorAction> {
+        match self {
+            PacmanError::Signature => vec![ErrorAction::RepairKeyring],
+            PacmanError::DiskSpace => vec![ErrorAction::CleanCache, ErrorAction::Retry],
+            PacmanError::Lock => vec![ErrorAction::RemoveLock, ErrorAction::Retry],
+            PacmanError::Network => vec![ErrorAction::EditRepositories, ErrorAction::Retry],
+            PacmanError::ConflictingFiles(_) => Vec::new(),
+            _ => vec![ErrorAction::Retry],
+        }
+    }
+}
+
+fn collect(output: &str, parse: impl Fn(&str) -> Option<String>) -> Vec<String> {
+    let mut found: Vec<String> = Vec::new();
+    for line in output.lines() {
+        let line = line.trim().trim_start_matches(":: ").trim_start_matches("error: ").trim_start_matches("warning: ");
+        if let Some(value) = parse(line) {
+            if !found.contains(&value) {
+                found.push(value);
+            }
+        }
+    }
+    found
+}
+
+// At most ten lines, long lists of files would not fit a dialog.
+fn preview(lines: &[String]) -> String {
+    let mut text = lines.iter().take(10).cloned().collect::<Vec<_>>().join("\n");
+    if lines.len() > 10 {
+        text.push_str(&format!("\n… and {} more", lines.len() - 10));
+    }
+    text
+}
+
+// Only removes the lock when no pacman process is left that could hold it.
+pub fn remove_lock() -> bool {
+    let running = runner::command("pgrep")
+        .args(["-x", "pacman"])
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false);
+    if running {
+        eprintln!("pacman is still running, keeping {}", LOCK_FILE);
+        return false;
+    }
+
+    match runner::command("pkexec").args(["rm", "-f", LOCK_FILE]).status() {
+        Ok(status) => status.success(),
+        Err(e) => {
+            eprintln!("Failed to even launch pkexec: {}", e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Recorded with `LC_ALL=C pacman -Syu 2>&1`, see tests/pacman-errors.
+    fn sample(name: &str) -> String {
+        let path = format!("{}/tests/pacman-errors/{}.err", env!("CARGO_MANIFEST_DIR"), name);
+        std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path, e))
+    }
+
+    #[test]
+    fn conflicting_files() {
+        assert_eq!(
+            PacmanError::classify(&sample("conflicting-files")),
+            PacmanError::ConflictingFiles(vec![
+                "/usr/lib/python3.12/site-packages/pip/__init__.py".to_string(),
+                "/usr/lib/python3.12/site-packages/pip/__main__.py".to_string(),
+                "/usr/lib/node_modules/npm/package.json".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn conflicting_files_from_the_scenario() {
+        let output = include_str!("../tests/scenarios/conflicting-files/pacman-Syu.err");
+        assert_eq!(
+            PacmanError::classify(output),
+            PacmanError::ConflictingFiles(vec![
+                "/usr/bin/vimtutor".to_string(),
+                "/usr/share/vim/vim91/doc/tags".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn conflicting_files_from_libalpm() {
+        let output = "error: failed to commit transaction (conflicting files)\n";
+        assert_eq!(PacmanError::classify(output), PacmanError::ConflictingFiles(Vec::new()));
+    }
+
+    #[test]
+    fn unresolvable_dependency() {
+        assert_eq!(
+            PacmanError::classify(&sample("unresolvable-dependency")),
+            PacmanError::UnresolvableDependencies(vec![
+                "libicuuc.so=74-64 required by boost-libs".to_string(),
+                "python<3.13 required by python-pyqt5-sip".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn target_not_found() {
+        assert_eq!(
+            PacmanError::classify("error: target not found: python-foo\n"),
+            PacmanError::UnresolvableDependencies(vec!["python-foo (not found)".to_string()])
+        );
+    }
+
+    #[test]
+    fn db_lock() {
+        let error = PacmanError::classify(&sample("db-lock"));
+        assert_eq!(error, PacmanError::Lock);
+        assert_eq!(error.actions(), vec![ErrorAction::RemoveLock, ErrorAction::Retry]);
+    }
+
+    #[test]
+    fn package_conflict() {
+        let error = PacmanError::classify(&sample("package-conflict"));
+        assert_eq!(error, PacmanError::PackageConflict(vec!["pipewire-jack and jack2".to_string()]));
+        assert_eq!(error.actions(), vec![ErrorAction::Retry]);
+    }
+
+    #[test]
+    fn interrupted_transaction() {
+        assert_eq!(PacmanError::classify(&sample("interrupted-transaction")), PacmanError::Interrupted);
+    }
+
+    // The next run after pacman was killed halfway, the lock it left behind
+    // already removed.
+    #[test]
+    fn interrupted_database() {
+        let error = PacmanError::classify(&sample("interrupted-database"));
+        assert_eq!(error, PacmanError::Interrupted);
+        assert_eq!(error.actions(), vec![ErrorAction::Retry]);
+    }
+
+    #[test]
+    fn signature() {
+        let error = PacmanError::classify(&sample("signature"));
+        assert_eq!(error, PacmanError::Signature);
+        assert_eq!(error.actions(), vec![ErrorAction::RepairKeyring]);
+    }
+
+    #[test]
+    fn disk_full() {
+        assert_eq!(PacmanError::classify(&sample("disk-full")), PacmanError::DiskSpace);
+    }
+
+    #[test]
+    fn mirror_failure() {
+        assert_eq!(PacmanError::classify(&sample("mirror-failure")), PacmanError::Network);
+    }
+
+    #[test]
+    fn unknown_keeps_the_last_error() {
+        let output = "error: something new\nerror: failed to commit transaction (something newer)\n";
+        assert_eq!(
+            PacmanError::classify(output),
+            PacmanError::Unknown("failed to commit transaction (something newer)".to_string())
+        );
+    }
+}
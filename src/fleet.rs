@@ -0,0 +1,256 @@
+use gtk::{gio, glib};
+use gtk::prelude::*;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use crate::report::{Report, ReportFormat};
+use crate::runner;
+use crate::update_manager::UpdateManager;
+
+// Bumped whenever a field of the payload changes meaning or goes away,
+// added fields keep the version.
+pub const SCHEMA_VERSION: u32 = 1;
+pub const CLI_ARG: &str = "--fleet-report";
+
+const ATTEMPTS: u32 = 3;
+// Doubled after every failed attempt.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+// Payloads that could not be sent wait for the next report, the oldest
+// ones are dropped past this many.
+const OUTBOX_LIMIT: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FleetEvent {
+    Check,
+    Upgrade,
+}
+
+impl FleetEvent {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "check" => Some(FleetEvent::Check),
+            "upgrade" => Some(FleetEvent::Upgrade),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            FleetEvent::Check => "check",
+            FleetEvent::Upgrade => "upgrade",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FleetConfig {
+    pub endpoint: String,
+    // Sent as a bearer token, empty for endpoints without authentication.
+    pub token: String,
+}
+
+impl FleetConfig {
+    // None unless an endpoint is configured, reporting is off by default.
+    pub fn load() -> Option<Self> {
+        let settings = gio::Settings::new("org.gnome.Example");
+        let endpoint = settings.string("fleet-endpoint").trim().to_string();
+        (!endpoint.is_empty()).then(|| Self {
+            endpoint,
+            token: settings.string("fleet-token").to_string(),
+        })
+    }
+}
+
+// The report as written by the export, wrapped with what the receiving end
+// needs to tell payloads apart.
+pub fn payload(event: FleetEvent, report: &Report) -> String {
+    format!(
+        "{{\n  \"schema_version\": {},\n  \"event\": \"{}\",\n  \"sent\": {},\n  \"report\": {}\n}}\n",
+        SCHEMA_VERSION,
+        event.name(),
+        glib::real_time() / 1_000_000,
+        report.render(ReportFormat::Json).trim_end())
+}
+
+// Queues the report and sends everything queued, returns false when
+// something is left in the outbox.
+pub fn send(config: &FleetConfig, event: FleetEvent, report: &Report) -> bool {
+    queue(event, report);
+    flush(config)
+}
+
+fn outbox() -> PathBuf {
+    glib::user_state_dir().join("updater-new").join("fleet-outbox")
+}
+
+fn queue(event: FleetEvent, report: &Report) {
+    let dir = outbox();
+    if let Err(e) = fs::create_dir_all(&dir) {
+        eprintln!("Failed to create {}: {}", dir.display(), e);
+        return;
+    }
+    // Named by time, so the outbox sorts oldest first.
+    let path = dir.join(format!("{:020}-{}.json", glib::real_time(), event.name()));
+    if let Err(e) = fs::write(&path, payload(event, report)) {
+        eprintln!("Failed to write {}: {}", path.display(), e);
+    }
+}
+
+// "json" for queued payloads, "sending" for the ones a flush claimed.
+fn queued(extension: &str) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(outbox())
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|found| found == extension))
+        .collect();
+    paths.sort();
+    paths
+}
+
+// The check and the upgrade report each flush on their own thread, and the
+// timer's `--fleet-report` may run next to the window. The lock makes them
+// take turns, so no payload is read and sent twice.
+fn lock_outbox() -> Option<fs::File> {
+    let path = outbox().join(".lock");
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .map_err(|e| eprintln!("Failed to open {}: {}", path.display(), e))
+        .ok()?;
+    file.lock().map_err(|e| eprintln!("Failed to lock {}: {}", path.display(), e)).ok()?;
+    Some(file)
+}
+
+fn flush(config: &FleetConfig) -> bool {
+    let Some(_lock) = lock_outbox() else {
+        return false;
+    };
+    // Claims a flush left behind when it was killed, nobody else holds the lock.
+    for path in queued("sending") {
+        let _ = fs::rename(&path, path.with_extension(""));
+    }
+
+    let mut paths = queued("json");
+    if paths.len() > OUTBOX_LIMIT {
+        for path in paths.drain(..paths.len() - OUTBOX_LIMIT) {
+            eprintln!("Dropping unsent fleet report {}", path.display());
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    for path in paths {
+        // Renamed before sending, a payload is either queued or being sent.
+        let claimed = path.with_extension("json.sending");
+        if fs::rename(&path, &claimed).is_err() {
+            continue;
+        }
+        let mut delay = RETRY_DELAY;
+        let mut attempt = 1;
+        loop {
+            match post(config, &claimed) {
+                Ok(()) => {
+                    let _ = fs::remove_file(&claimed);
+                    break;
+                }
+                Err(e) if attempt < ATTEMPTS => {
+                    eprintln!("Sending the fleet report failed (attempt {} of {}): {}", attempt, ATTEMPTS, e);
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    // Keeps the order, later reports wait behind this one.
+                    eprintln!("Sending the fleet report failed, keeping it for later: {}", e);
+                    let _ = fs::rename(&claimed, &path);
+                    return false;
+                }
+            }
+        }
+    }
+    true
+}
+
+// The endpoint and token go to curl on stdin, so the token doesn't show up
+// in the process list.
+fn post(config: &FleetConfig, path: &Path) -> Result<(), String> {
+    let mut curl_config = format!("url = \"{}\"\n", curl_escape(&config.endpoint));
+    if !config.token.is_empty() {
+        curl_config.push_str(&format!("header = \"Authorization: Bearer {}\"\n", curl_escape(&config.token)));
+    }
+
+    let mut child = runner::command("curl")
+        .args(["--silent", "--show-error", "--fail", "--max-time", "30", "--config", "-"])
+        .args(["--header", "Content-Type: application/json"])
+        .args(["--header", &format!("X-Updater-Schema: {}", SCHEMA_VERSION)])
+        .arg("--data-binary")
+        .arg(format!("@{}", path.display()))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to even launch curl: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(curl_config.as_bytes());
+    }
+
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+fn curl_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// `updater-new --fleet-report [check|upgrade]` sends one report right away,
+// e.g. from a systemd timer on machines nobody logs into.
+pub fn run_cli(args: &[String]) -> i32 {
+    let event = match args {
+        [] => Some(FleetEvent::Check),
+        [name] => FleetEvent::from_name(name),
+        _ => None,
+    };
+    let Some(event) = event else {
+        eprintln!("{} takes check or upgrade", CLI_ARG);
+        return 2;
+    };
+    let Some(config) = FleetConfig::load() else {
+        eprintln!("No fleet endpoint configured, set the fleet-endpoint key");
+        return 2;
+    };
+
+    let report = match event {
+        FleetEvent::Check => {
+            let (sender, receiver) = mpsc::channel();
+            UpdateManager::check_updates(sender);
+            match receiver.recv() {
+                Ok(Ok(updates)) => Report::pending(&updates),
+                Ok(Err(e)) => {
+                    eprintln!("Failed to check for updates: {}", e);
+                    return 1;
+                }
+                Err(_) => return 1,
+            }
+        }
+        FleetEvent::Upgrade => match Report::last_transaction() {
+            Some(report) => report,
+            None => {
+                eprintln!("No transaction found in pacman's log");
+                return 1;
+            }
+        },
+    };
+
+    if send(&config, event, &report) { 0 } else { 1 }
+}
@@ -0,0 +1,85 @@
+use gtk::gio;
+use gtk::prelude::*;
+
+// Held packages are never upgraded until released, a skipped version is
+// only held back until a newer one shows up. Both are stored in the app
+// settings and passed to pacman as --ignore.
+#[derive(Debug, Clone, Default)]
+pub struct Holds {
+    pub held: Vec<String>,
+    // "name=version" entries.
+    pub skipped: Vec<String>,
+}
+
+impl Holds {
+    pub fn load() -> Self {
+        let settings = settings();
+        Self {
+            held: strv(&settings, "held-packages"),
+            skipped: strv(&settings, "skipped-versions"),
+        }
+    }
+
+    pub fn is_held(&self, name: &str, version: &str) -> bool {
+        self.held.iter().any(|held| held == name)
+            || self.skipped.iter().any(|skipped| *skipped == skip_entry(name, version))
+    }
+}
+
+fn settings() -> gio::Settings {
+    gio::Settings::new("org.gnome.Example")
+}
+
+fn strv(settings: &gio::Settings, key: &str) -> Vec<String> {
+    settings.strv(key).iter().map(|value| value.to_string()).collect()
+}
+
+fn set_strv(settings: &gio::Settings, key: &str, values: &[String]) {
+    let values: Vec<&str> = values.iter().map(String::as_str).collect();
+    let _ = settings.set_strv(key, values);
+}
+
+fn skip_entry(name: &str, version: &str) -> String {
+    format!("{}={}", name, version)
+}
+
+pub fn hold(name: &str) {
+    let settings = settings();
+    let mut held = strv(&settings, "held-packages");
+    if !held.iter().any(|pkg| pkg == name) {
+        held.push(name.to_string());
+        set_strv(&settings, "held-packages", &held);
+    }
+}
+
+pub fn skip_version(name: &str, version: &str) {
+    let settings = settings();
+    // Only the latest skipped version of a package matters.
+    let prefix = format!("{}=", name);
+    let mut skipped: Vec<String> = strv(&settings, "skipped-versions")
+        .into_iter()
+        .filter(|entry| !entry.starts_with(&prefix))
+        .collect();
+    skipped.push(skip_entry(name, version));
+    set_strv(&settings, "skipped-versions", &skipped);
+}
+
+pub fn release(name: &str) {
+    let settings = settings();
+    let prefix = format!("{}=", name);
+    let held: Vec<String> = strv(&settings, "held-packages").into_iter().filter(|pkg| pkg != name).collect();
+    let skipped: Vec<String> = strv(&settings, "skipped-versions")
+        .into_iter()
+        .filter(|entry| !entry.starts_with(&prefix))
+        .collect();
+    set_strv(&settings, "held-packages", &held);
+    set_strv(&settings, "skipped-versions", &skipped);
+}
+
+pub fn ignore_args(names: &[String]) -> Vec<String> {
+    if names.is_empty() {
+        Vec::new()
+    } else {
+        vec!["--ignore".to_string(), names.join(",")]
+    }
+}
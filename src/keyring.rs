@@ -0,0 +1,136 @@
+use std::fs;
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+use crate::pacman;
+use crate::runner;
+
+pub const KEYRING_PACKAGE: &str = "archlinux-keyring";
+// Installed together with the package, its age is the keyring's age.
+const KEYRING_FILE: &str = "/usr/share/pacman/keyrings/archlinux.gpg";
+// Without a sync database to compare with, a keyring this old is suspect.
+// New releases come out every few weeks.
+const KEYRING_MAX_AGE_DAYS: u64 = 90;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyringState {
+    Current,
+    // The sync database has a newer archlinux-keyring.
+    Outdated { installed: String, available: String },
+    // No newer version known, but it wasn't updated for this many days.
+    Old { days: u64 },
+}
+
+// Runs before a full upgrade. Compares the installed keyring with the
+// freshly synced database checkupdates left behind, falls back to the
+// keyring's age without one. Systems without archlinux-keyring count as
+// current, they are not using Arch's keys.
+pub fn freshness() -> KeyringState {
+    let Some(installed) = pacman::local_info(&[KEYRING_PACKAGE]).remove(KEYRING_PACKAGE) else {
+        return KeyringState::Current;
+    };
+
+    if pacman::checkupdates_dbpath().is_some() {
+        if let Some(available) = pacman::sync_info(&[KEYRING_PACKAGE]).remove(KEYRING_PACKAGE) {
+            return if pacman::is_newer(&available.version, &installed.version) {
+                KeyringState::Outdated { installed: installed.version, available: available.version }
+            } else {
+                KeyringState::Current
+            };
+        }
+    }
+
+    let age = fs::metadata(KEYRING_FILE)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .unwrap_or(Duration::ZERO);
+    let days = age.as_secs() / (24 * 60 * 60);
+    if days >= KEYRING_MAX_AGE_DAYS {
+        KeyringState::Old { days }
+    } else {
+        KeyringState::Current
+    }
+}
+
+// What pacman prints when a package signature can't be checked, most of the
+// time because archlinux-keyring is older than the packager's key.
+const SIGNATURE_ERRORS: [&str; 6] = [
+    "(PGP signature)",
+    "is unknown trust",
+    "is marked as invalid",
+    "unknown public key",
+    "could not be looked up remotely",
+    "required key missing from keyring",
+];
+
+pub fn is_signature_error(output: &str) -> bool {
+    SIGNATURE_ERRORS.iter().any(|error| output.contains(error))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyringFix {
+    // Installs the newest archlinux-keyring on its own.
+    UpdateKeyring,
+    // Recreates the local keyring from the installed archlinux-keyring.
+    Populate,
+    // Fetches updated keys from the key servers, this can take minutes.
+    RefreshKeys,
+}
+
+impl KeyringFix {
+    pub fn from_response(response: &str) -> Option<Self> {
+        match response {
+            "keyring" => Some(KeyringFix::UpdateKeyring),
+            "populate" => Some(KeyringFix::Populate),
+            "refresh" => Some(KeyringFix::RefreshKeys),
+            _ => None,
+        }
+    }
+
+    pub fn run(&self) -> bool {
+        let mut command = runner::command("pkexec");
+        match self {
+            KeyringFix::UpdateKeyring => {
+                command.args(["pacman", "-Sy", "--needed", "--noconfirm", KEYRING_PACKAGE]);
+            }
+            KeyringFix::Populate => {
+                command.args(["sh", "-c", "pacman-key --init && pacman-key --populate archlinux"]);
+            }
+            KeyringFix::RefreshKeys => {
+                command.args(["pacman-key", "--refresh-keys"]);
+            }
+        }
+
+        match command.output() {
+            Ok(output) => {
+                if !output.status.success() {
+                    eprintln!("Keyring repair failed with exit code: {}", output.status);
+                    eprintln!("Stderr: {}", String::from_utf8_lossy(&output.stderr));
+                }
+                output.status.success()
+            }
+            Err(e) => {
+                eprintln!("Failed to even launch pkexec: {}", e);
+                false
+            }
+        }
+    }
+}
+
+// `pkexec pacman -Syu`, installing archlinux-keyring first when an update for
+// it is pending (or freshness() found it outdated) so the rest of the
+// transaction is checked with the new keys. Callers append the remaining
+// pacman arguments, both steps get them so `--ignore` holds for the keyring
+// step too. `pending` must not contain held packages.
+#[cfg_attr(feature = "alpm", allow(dead_code))]
+pub fn system_upgrade(pending: &[String]) -> Command {
+    let mut command = runner::command("pkexec");
+    if pending.iter().any(|name| name == KEYRING_PACKAGE) {
+        let script = format!("pacman -Sy --needed --noconfirm {} \"$@\" && exec pacman -Su \"$@\"", KEYRING_PACKAGE);
+        command.args(["sh", "-c", &script, "sh"]);
+    } else {
+        command.args(["pacman", "-Syu"]);
+    }
+    command
+}
@@ -18,14 +18,39 @@
  * SPDX-License-Identifier: GPL-3.0-or-later
  */
 
+#[cfg(feature = "alpm")]
+mod alpm_backend;
+mod app_state;
 mod application;
+mod auto_update;
+mod boot_check;
+mod conditions;
 mod config;
+mod disk_space;
+mod errors;
+mod fleet;
+mod holds;
+mod keyring;
+mod offline_update;
+mod orphans_dialog;
+mod pacman;
+mod pacman_conf;
+mod prefetch;
+mod question_dialog;
+mod reboot;
+mod report;
+mod repos_dialog;
+mod runner;
+mod state_store;
+mod transaction;
+mod update_item;
 mod update_manager;
 mod update_row;
+mod update_section;
+mod update_summary;
 mod window;
 
 use self::application::UpdaterNewApplication;
-use self::window::UpdaterWindow;
 
 use config::{GETTEXT_PACKAGE, LOCALEDIR};
 use gettextrs::{bind_textdomain_codeset, bindtextdomain, textdomain};
@@ -39,6 +64,37 @@ fn main() -> glib::ExitCode {
         .expect("Unable to set the text domain encoding");
     textdomain(GETTEXT_PACKAGE).expect("Unable to switch to the text domain");
 
+    if let Some(dir) = std::env::var_os(runner::FAKE_BIN_ENV) {
+        eprintln!("Running system commands from {}", std::path::Path::new(&dir).display());
+        runner::set_runner(runner::FakeBinRunner {
+            dir: dir.into(),
+            modules: std::env::var_os(runner::FAKE_MODULES_ENV).map(Into::into),
+        });
+    }
+
+    // Modes that run without any window, e.g. from the systemd units we ship.
+    // Only the first argument picks one: the helpers run through pkexec and
+    // polkit only vouches for that one, the rest is checked by each mode.
+    let args: Vec<String> = std::env::args().collect();
+    let rest = args.get(2..).unwrap_or_default();
+    let code = match args.get(1).map(String::as_str) {
+        Some(mode @ (offline_update::CLI_ARG | auto_update::CLI_ARG)) if !rest.is_empty() => {
+            eprintln!("{} takes no arguments", mode);
+            Some(2)
+        }
+        Some(offline_update::CLI_ARG) => Some(offline_update::apply()),
+        Some(auto_update::CLI_ARG) => Some(auto_update::run_cli()),
+        Some(auto_update::HELPER_ARG) => Some(auto_update::run_helper(rest)),
+        Some(report::CLI_ARG) => Some(report::run_cli(rest)),
+        Some(fleet::CLI_ARG) => Some(fleet::run_cli(rest)),
+        #[cfg(feature = "alpm")]
+        Some(alpm_backend::HELPER_ARG) => Some(alpm_backend::run_helper(rest)),
+        _ => None,
+    };
+    if let Some(code) = code {
+        return glib::ExitCode::from(code);
+    }
+
     // Load resources
     let resources_bytes = include_bytes!(concat!(env!("OUT_DIR"), "/updater-new.gresource"));
     let resources = gio::Resource::from_data(&glib::Bytes::from_static(resources_bytes))
@@ -0,0 +1,159 @@
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::holds;
+use crate::runner;
+
+// Offline updates follow systemd's system-update.target convention: the
+// /system-update symlink makes the next boot enter the update target, where
+// our unit installs the packages downloaded beforehand with `pacman -Syuw`.
+pub const TRIGGER: &str = "/system-update";
+pub const STATE_DIR: &str = "/var/lib/updater-new";
+pub const RESULT_FILE: &str = "/var/lib/updater-new/offline-update-result";
+pub const LOG_FILE: &str = "/var/lib/updater-new/offline-update.log";
+// What updater-new-offline-update.service runs.
+pub const CLI_ARG: &str = "--apply-offline-update";
+pub const IGNORE_FILE: &str = "/var/lib/updater-new/offline-update-ignore";
+
+#[derive(Debug, Clone, Default)]
+pub struct OfflineResult {
+    pub success: bool,
+    pub finished: i64,
+    pub packages: Vec<String>,
+}
+
+pub fn is_scheduled() -> bool {
+    fs::read_link(TRIGGER)
+        .map(|target| target == Path::new(STATE_DIR))
+        .unwrap_or(false)
+}
+
+// Downloads everything and arms the trigger in a single privileged call.
+// `ignored` (held packages) is kept next to the trigger for the boot time run.
+pub fn schedule(ignored: &[String]) -> bool {
+    let script = format!(
+        "pacman -Syuw --noconfirm && mkdir -p {dir} && printf '%s\\n' \"$@\" > {ignore} && ln -sfn {dir} {trigger}",
+        dir = STATE_DIR,
+        ignore = IGNORE_FILE,
+        trigger = TRIGGER,
+    );
+    match runner::command("pkexec")
+        .args(["sh", "-c", &script, "sh"])
+        .args(ignored)
+        .output()
+    {
+        Ok(output) => {
+            if !output.status.success() {
+                eprintln!("Command ran but failed with exit code: {}", output.status);
+                eprintln!("Stderr: {}", String::from_utf8_lossy(&output.stderr));
+            }
+            output.status.success()
+        }
+        Err(e) => {
+            eprintln!("Failed to even launch pkexec: {}", e);
+            false
+        }
+    }
+}
+
+pub fn cancel() -> bool {
+    match runner::command("pkexec").args(["rm", "-f", TRIGGER]).status() {
+        Ok(status) => status.success(),
+        Err(e) => {
+            eprintln!("Failed to even launch pkexec: {}", e);
+            false
+        }
+    }
+}
+
+// Entry point of `updater-new --apply-offline-update`, run as root by
+// updater-new-offline-update.service early during boot.
+pub fn apply() -> i32 {
+    if !is_scheduled() {
+        eprintln!("No offline update scheduled by updater-new");
+        return 0;
+    }
+
+    // Remove the trigger first so a crash can never cause a boot loop.
+    if let Err(e) = fs::remove_file(TRIGGER) {
+        eprintln!("Failed to remove {}: {}", TRIGGER, e);
+        return 1;
+    }
+
+    let packages: Vec<String> = runner::command("pacman")
+        .env("LC_ALL", "C")
+        .args(["-Qu", "-q"])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect())
+        .unwrap_or_default();
+
+    let ignored: Vec<String> = fs::read_to_string(IGNORE_FILE)
+        .map(|contents| contents.lines().filter(|line| !line.is_empty()).map(str::to_string).collect())
+        .unwrap_or_default();
+
+    let success = match runner::command("pacman")
+        .args(["-Su", "--noconfirm", "--noprogressbar"])
+        .args(holds::ignore_args(&ignored))
+        .output()
+    {
+        Ok(output) => {
+            let log = format!("{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+            print!("{}", log);
+            let _ = fs::write(LOG_FILE, log);
+            output.status.success()
+        }
+        Err(e) => {
+            eprintln!("Failed to launch pacman: {}", e);
+            let _ = fs::write(LOG_FILE, e.to_string());
+            false
+        }
+    };
+
+    let result = OfflineResult {
+        success,
+        finished: now(),
+        packages,
+    };
+    if let Err(e) = fs::write(RESULT_FILE, format_result(&result)) {
+        eprintln!("Failed to write {}: {}", RESULT_FILE, e);
+    }
+
+    let _ = runner::command("systemctl").arg("reboot").status();
+    if success { 0 } else { 1 }
+}
+
+pub fn read_result() -> Option<OfflineResult> {
+    parse_result(&fs::read_to_string(RESULT_FILE).ok()?)
+}
+
+fn format_result(result: &OfflineResult) -> String {
+    format!(
+        "status={}\nfinished={}\npackages={}\n",
+        if result.success { "ok" } else { "failed" },
+        result.finished,
+        result.packages.join(" "),
+    )
+}
+
+fn parse_result(contents: &str) -> Option<OfflineResult> {
+    let mut result = OfflineResult::default();
+    for line in contents.lines() {
+        match line.split_once('=') {
+            Some(("status", value)) => result.success = value == "ok",
+            Some(("finished", value)) => result.finished = value.parse().ok()?,
+            Some(("packages", value)) => {
+                result.packages = value.split_whitespace().map(str::to_string).collect()
+            }
+            _ => {}
+        }
+    }
+    (result.finished > 0).then_some(result)
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0)
+}
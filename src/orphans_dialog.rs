@@ -0,0 +1,282 @@
+use adw::prelude::*;
+use adw::subclass::prelude::*;
+use gtk::{gio, glib};
+use crate::pacman::{self, PackageInfo};
+use crate::update_manager::UpdateManager;
+use std::cell::{OnceCell, RefCell};
+
+mod imp {
+    use super::*;
+
+    #[derive(Default)]
+    pub struct OrphansDialog {
+        pub settings: OnceCell<gio::Settings>,
+        pub list: gtk::ListBox,
+        pub status: gtk::Label,
+        pub remove_button: gtk::Button,
+        pub rows: RefCell<Vec<(String, gtk::CheckButton)>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for OrphansDialog {
+        const NAME: &'static str = "OrphansDialog";
+        type Type = super::OrphansDialog;
+        type ParentType = adw::Dialog;
+    }
+
+    impl ObjectImpl for OrphansDialog {
+        fn constructed(&self) {
+            self.parent_constructed();
+            let obj = self.obj();
+            self.settings.set(gio::Settings::new("org.gnome.Example")).unwrap();
+            obj.setup_ui();
+            obj.load();
+        }
+    }
+
+    impl WidgetImpl for OrphansDialog {}
+    impl AdwDialogImpl for OrphansDialog {}
+}
+
+glib::wrapper! {
+    pub struct OrphansDialog(ObjectSubclass<imp::OrphansDialog>)
+        @extends gtk::Widget, adw::Dialog;
+}
+
+impl OrphansDialog {
+    pub fn new() -> Self {
+        glib::Object::builder()
+            .property("title", "Orphaned Packages")
+            .property("content-width", 600)
+            .property("content-height", 500)
+            .build()
+    }
+
+    fn settings(&self) -> &gio::Settings {
+        self.imp().settings.get().unwrap()
+    }
+
+    fn setup_ui(&self) {
+        let imp = self.imp();
+
+        imp.list.set_selection_mode(gtk::SelectionMode::None);
+        imp.list.add_css_class("boxed-list");
+
+        let scrolled = gtk::ScrolledWindow::builder()
+            .hexpand(true)
+            .vexpand(true)
+            .child(&imp.list)
+            .build();
+
+        imp.status.set_halign(gtk::Align::Center);
+        imp.status.set_wrap(true);
+
+        imp.remove_button.set_label("Remove Selected");
+        imp.remove_button.set_halign(gtk::Align::End);
+        imp.remove_button.add_css_class("destructive-action");
+        imp.remove_button.connect_clicked(glib::clone!(#[weak(rename_to = obj)] self, move |_| {
+            obj.preview_selected();
+        }));
+
+        let content = gtk::Box::builder()
+            .orientation(gtk::Orientation::Vertical)
+            .spacing(12)
+            .margin_start(12)
+            .margin_end(12)
+            .margin_top(12)
+            .margin_bottom(12)
+            .build();
+        content.append(&scrolled);
+        content.append(&imp.status);
+        content.append(&imp.remove_button);
+
+        let toolbar = adw::ToolbarView::new();
+        toolbar.add_top_bar(&adw::HeaderBar::new());
+        toolbar.set_content(Some(&content));
+        self.set_child(Some(&toolbar));
+    }
+
+    fn load(&self) {
+        let imp = self.imp();
+        while let Some(child) = imp.list.first_child() {
+            imp.list.remove(&child);
+        }
+        imp.rows.borrow_mut().clear();
+        imp.status.set_text("Looking for orphans...");
+        imp.remove_button.set_sensitive(false);
+
+        let (sender, receiver) = std::sync::mpsc::channel::<Vec<PackageInfo>>();
+        UpdateManager::find_orphans(sender);
+
+        glib::timeout_add_local(std::time::Duration::from_millis(100),
+            glib::clone!(#[weak(rename_to = obj)] self, #[upgrade_or] glib::ControlFlow::Break, move || {
+                if let Ok(orphans) = receiver.try_recv() {
+                    obj.show_orphans(orphans);
+                    glib::ControlFlow::Break
+                } else {
+                    glib::ControlFlow::Continue
+                }
+            })
+        );
+    }
+
+    fn show_orphans(&self, orphans: Vec<PackageInfo>) {
+        let imp = self.imp();
+
+        if orphans.is_empty() {
+            imp.status.set_text("No orphaned packages");
+            return;
+        }
+
+        let total: u64 = orphans.iter().map(|pkg| pkg.installed_size).sum();
+        imp.status.set_text(&format!("{} orphans using {}", orphans.len(), pacman::format_size(total)));
+        imp.remove_button.set_sensitive(true);
+
+        let kept = self.settings().strv("kept-packages");
+
+        for pkg in orphans {
+            let is_kept = kept.iter().any(|name| name.as_str() == pkg.name);
+
+            let row = adw::ActionRow::builder()
+                .title(&pkg.name)
+                .subtitle(format!("{} · {} — {}",
+                    pkg.version,
+                    pacman::format_size(pkg.installed_size),
+                    pkg.description))
+                .build();
+
+            let check = gtk::CheckButton::builder()
+                .valign(gtk::Align::Center)
+                .sensitive(!is_kept)
+                .build();
+            row.add_prefix(&check);
+            row.set_activatable_widget(Some(&check));
+
+            let keep_button = gtk::ToggleButton::builder()
+                .label("Keep")
+                .valign(gtk::Align::Center)
+                .active(is_kept)
+                .tooltip_text("Never offer this package for removal")
+                .build();
+            let name = pkg.name.clone();
+            keep_button.connect_toggled(glib::clone!(#[weak(rename_to = obj)] self, #[weak] check, move |button| {
+                obj.set_kept(&name, button.is_active());
+                if button.is_active() {
+                    check.set_active(false);
+                }
+                check.set_sensitive(!button.is_active());
+            }));
+            row.add_suffix(&keep_button);
+
+            imp.list.append(&row);
+            imp.rows.borrow_mut().push((pkg.name, check));
+        }
+    }
+
+    fn set_kept(&self, name: &str, kept: bool) {
+        let mut packages: Vec<String> = self.settings()
+            .strv("kept-packages")
+            .iter()
+            .map(|pkg| pkg.to_string())
+            .filter(|pkg| pkg != name)
+            .collect();
+        if kept {
+            packages.push(name.to_string());
+        }
+        let packages: Vec<&str> = packages.iter().map(String::as_str).collect();
+        let _ = self.settings().set_strv("kept-packages", packages);
+    }
+
+    fn kept_packages(&self) -> Vec<String> {
+        self.settings().strv("kept-packages").iter().map(|pkg| pkg.to_string()).collect()
+    }
+
+    fn preview_selected(&self) {
+        let imp = self.imp();
+        let selected: Vec<String> = imp.rows.borrow()
+            .iter()
+            .filter(|(_, check)| check.is_active())
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        if selected.is_empty() {
+            imp.status.set_text("Select the packages to remove first");
+            return;
+        }
+
+        imp.remove_button.set_sensitive(false);
+        imp.status.set_text("Calculating what will be removed...");
+
+        let (sender, receiver) = std::sync::mpsc::channel::<Result<Vec<String>, String>>();
+        UpdateManager::preview_removal(selected.clone(), sender);
+
+        glib::timeout_add_local(std::time::Duration::from_millis(100),
+            glib::clone!(#[weak(rename_to = obj)] self, #[upgrade_or] glib::ControlFlow::Break, move || {
+                if let Ok(result) = receiver.try_recv() {
+                    obj.imp().remove_button.set_sensitive(true);
+                    match result {
+                        Ok(targets) => obj.confirm_removal(selected.clone(), targets),
+                        Err(error) => obj.imp().status.set_text(&format!("Cannot remove: {}", error)),
+                    }
+                    glib::ControlFlow::Break
+                } else {
+                    glib::ControlFlow::Continue
+                }
+            })
+        );
+    }
+
+    fn confirm_removal(&self, selected: Vec<String>, targets: Vec<String>) {
+        let kept = self.kept_packages();
+        let protected: Vec<&String> = targets.iter().filter(|pkg| kept.contains(pkg)).collect();
+        if !protected.is_empty() {
+            let names: Vec<&str> = protected.iter().map(|pkg| pkg.as_str()).collect();
+            self.imp().status.set_text(&format!(
+                "Removal would also take kept packages: {}", names.join(", ")));
+            return;
+        }
+
+        let dialog = adw::AlertDialog::builder()
+            .heading(format!("Remove {} Packages?", targets.len()))
+            .body(format!("The following packages will be removed recursively:\n\n{}", targets.join("\n")))
+            .close_response("cancel")
+            .default_response("cancel")
+            .build();
+        dialog.add_response("cancel", "Cancel");
+        dialog.add_response("remove", "Remove");
+        dialog.set_response_appearance("remove", adw::ResponseAppearance::Destructive);
+
+        dialog.connect_response(None, glib::clone!(#[weak(rename_to = obj)] self, move |_, response| {
+            if response == "remove" {
+                obj.remove(selected.clone());
+            }
+        }));
+
+        dialog.present(Some(self));
+    }
+
+    fn remove(&self, selected: Vec<String>) {
+        let imp = self.imp();
+        imp.status.set_text("Removing...");
+        imp.remove_button.set_sensitive(false);
+
+        let (sender, receiver) = std::sync::mpsc::channel::<String>();
+        UpdateManager::remove_packages(selected, sender);
+
+        glib::timeout_add_local(std::time::Duration::from_millis(100),
+            glib::clone!(#[weak(rename_to = obj)] self, #[upgrade_or] glib::ControlFlow::Break, move || {
+                if let Ok(status) = receiver.try_recv() {
+                    if status == "Ok" {
+                        obj.load();
+                    } else {
+                        obj.imp().status.set_text("Removal Failed, read the log");
+                        obj.imp().remove_button.set_sensitive(true);
+                    }
+                    glib::ControlFlow::Break
+                } else {
+                    glib::ControlFlow::Continue
+                }
+            })
+        );
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::io;
+use std::process::Output;
+
+use gtk::glib;
+
+use crate::runner;
+
+#[derive(Debug, Clone, Default)]
+pub struct PackageInfo {
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    pub repo: String,
+    pub download_size: u64,
+    pub installed_size: u64,
+}
+
+// Runs an unprivileged pacman query. The locale is forced to C so the
+// field names and size units we parse stay the same on every system.
+pub fn query(args: &[&str]) -> io::Result<Output> {
+    runner::command("pacman")
+        .env("LC_ALL", "C")
+        .args(args)
+        .output()
+}
+
+// Parses the `Key : Value` blocks printed by `pacman -Qi` and `pacman -Si`.
+pub fn parse_info(output: &str) -> Vec<PackageInfo> {
+    let mut packages = Vec::new();
+    let mut current = PackageInfo::default();
+
+    for line in output.lines() {
+        if line.trim().is_empty() {
+            if !current.name.is_empty() {
+                packages.push(std::mem::take(&mut current));
+            }
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once(" : ") else {
+            continue;
+        };
+        let value = value.trim();
+
+        match key.trim() {
+            "Name" => current.name = value.to_string(),
+            "Version" => current.version = value.to_string(),
+            "Description" => current.description = value.to_string(),
+            "Repository" => current.repo = value.to_string(),
+            "Download Size" => current.download_size = parse_size(value),
+            "Installed Size" => current.installed_size = parse_size(value),
+            _ => {}
+        }
+    }
+
+    if !current.name.is_empty() {
+        packages.push(current);
+    }
+
+    packages
+}
+
+// Turns pacman's human readable sizes ("1.50 MiB") back into bytes.
+pub fn parse_size(value: &str) -> u64 {
+    let mut parts = value.split_whitespace();
+    let number: f64 = parts.next().and_then(|n| n.parse().ok()).unwrap_or(0.0);
+    let multiplier: f64 = match parts.next().unwrap_or("B") {
+        "KiB" => 1024.0,
+        "MiB" => 1024.0 * 1024.0,
+        "GiB" => 1024.0 * 1024.0 * 1024.0,
+        "TiB" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        _ => 1.0,
+    };
+    (number * multiplier).max(0.0) as u64
+}
+
+pub fn format_size(bytes: u64) -> String {
+    glib::format_size(bytes).to_string()
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PendingUpdate {
+    pub name: String,
+    pub old_version: String,
+    pub new_version: String,
+    pub repo: String,
+    pub download_size: u64,
+    pub installed_delta: i64,
+    pub security: bool,
+}
+
+pub const AUR_REPO: &str = "aur";
+pub const FLATPAK_REPO: &str = "flatpak";
+
+// checkupdates exits with 2 when there is nothing to update, any other
+// failure means the check itself didn't work, e.g. no mirror answered.
+pub fn checkupdates() -> Result<Vec<PendingUpdate>, String> {
+    let output = runner::command("checkupdates").output().map_err(|e| e.to_string())?;
+    match output.status.code() {
+        Some(0) => Ok(parse_update_lines(&String::from_utf8_lossy(&output.stdout), "")),
+        Some(2) => Ok(Vec::new()),
+        _ => {
+            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            if stderr.is_empty() {
+                Err(format!("checkupdates failed with {}", output.status))
+            } else {
+                Err(stderr)
+            }
+        }
+    }
+}
+
+// Parses `pkg old -> new` lines as printed by checkupdates and `paru/yay -Qua`.
+pub fn parse_update_lines(output: &str, repo: &str) -> Vec<PendingUpdate> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let name = parts.next()?;
+            let old_version = parts.next().unwrap_or("");
+            let new_version = parts.next_back().unwrap_or(old_version);
+            Some(PendingUpdate {
+                name: name.to_string(),
+                old_version: old_version.to_string(),
+                new_version: new_version.to_string(),
+                repo: repo.to_string(),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+// checkupdates keeps a freshly synced copy of the databases in a throwaway
+// path. Reading from it gives the metadata of the versions that will actually
+// be installed instead of whatever the system databases last saw.
+pub fn checkupdates_dbpath() -> Option<String> {
+    if let Ok(path) = std::env::var("CHECKUPDATES_DB") {
+        return Some(path);
+    }
+
+    use std::os::unix::fs::MetadataExt;
+    let uid = std::fs::metadata("/proc/self").ok()?.uid();
+    let tmp = std::env::var("TMPDIR").unwrap_or_else(|_| "/tmp".to_string());
+    let path = format!("{}/checkup-db-{}", tmp, uid);
+    std::path::Path::new(&path).join("sync").is_dir().then_some(path)
+}
+
+// Sync database metadata (repository, sizes) of the versions to be installed.
+pub fn sync_info(names: &[&str]) -> HashMap<String, PackageInfo> {
+    let dbpath = checkupdates_dbpath();
+    let mut args = Vec::new();
+    if let Some(dbpath) = dbpath.as_deref() {
+        args.extend(["--dbpath", dbpath]);
+    }
+    args.push("-Si");
+    info_by_name(args, names)
+}
+
+pub fn local_info(names: &[&str]) -> HashMap<String, PackageInfo> {
+    info_by_name(vec!["-Qi"], names)
+}
+
+fn info_by_name<'a>(mut args: Vec<&'a str>, names: &[&'a str]) -> HashMap<String, PackageInfo> {
+    let mut packages = HashMap::new();
+    if names.is_empty() {
+        return packages;
+    }
+
+    args.extend(names);
+    let Ok(output) = query(&args) else {
+        return packages;
+    };
+
+    // A package living in several repos is printed once per repo, the first
+    // one is what pacman would pick.
+    for info in parse_info(&String::from_utf8_lossy(&output.stdout)) {
+        packages.entry(info.name.clone()).or_insert(info);
+    }
+    packages
+}
+
+// pacman's own version ordering, so "1:2.0" and "2.0-1" compare right.
+pub fn is_newer(version: &str, than: &str) -> bool {
+    runner::command("vercmp")
+        .args([version, than])
+        .output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).trim().parse::<i32>().is_ok_and(|order| order > 0))
+}
+
+// Names of pending updates that fix a known vulnerability, if arch-audit is installed.
+pub fn security_updates() -> Vec<String> {
+    if glib::find_program_in_path("arch-audit").is_none() {
+        return Vec::new();
+    }
+    match runner::command("arch-audit").args(["--upgradable", "-qq"]).output() {
+        Ok(res) => String::from_utf8_lossy(&res.stdout)
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+pub fn is_kernel_or_driver(name: &str) -> bool {
+    name.starts_with("linux")
+        || name.starts_with("nvidia")
+        || name.starts_with("xf86-video-")
+        || name.starts_with("vulkan-")
+        || name.ends_with("-dkms")
+        || name.ends_with("-firmware")
+        || name == "mesa"
+        || name == "lib32-mesa"
+}
+
+// Packages that can leave the machine unbootable when their hooks go wrong.
+// systemd-boot is part of the systemd package.
+pub fn is_boot_critical(name: &str) -> bool {
+    name.starts_with("linux")
+        || name.starts_with("nvidia")
+        || name.starts_with("mkinitcpio")
+        || name.ends_with("-dkms")
+        || matches!(name, "grub" | "systemd" | "systemd-boot")
+}
+
+// Official repositories first, then custom ones alphabetically, then the
+// sources that don't come from pacman at all.
+pub fn repo_order(repo: &str) -> (u8, String) {
+    let rank = match repo {
+        "core" => 0,
+        "extra" => 1,
+        "multilib" => 2,
+        AUR_REPO => 4,
+        FLATPAK_REPO => 5,
+        _ => 3,
+    };
+    (rank, repo.to_string())
+}
+
+pub fn repo_title(repo: &str) -> String {
+    match repo {
+        AUR_REPO => "AUR".to_string(),
+        FLATPAK_REPO => "Flatpak".to_string(),
+        "" => "Other".to_string(),
+        _ => repo.to_string(),
+    }
+}
+
+pub fn aur_helper() -> Option<&'static str> {
+    ["paru", "yay"]
+        .into_iter()
+        .find(|helper| glib::find_program_in_path(helper).is_some())
+}
@@ -0,0 +1,529 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+use crate::auto_update;
+use crate::runner;
+
+pub const PATH: &str = "/etc/pacman.conf";
+pub const OPTIONS: &str = "options";
+
+// Keys that may appear commented out inside a disabled repository section.
+const REPO_KEYS: [&str; 4] = ["Server", "Include", "SigLevel", "Usage"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Entry {
+    Section(String),
+    // Key and value, None for flags such as `Color`.
+    Option(String, Option<String>),
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Line {
+    text: String,
+    commented: bool,
+    entry: Entry,
+}
+
+impl Line {
+    fn parse(text: &str) -> Self {
+        let trimmed = text.trim();
+        let (commented, body) = match trimmed.strip_prefix('#') {
+            Some(body) => (true, body.trim()),
+            None => (false, trimmed),
+        };
+
+        // "#[multilib]" and "#Color" are disabled entries, "#   [repo-name]"
+        // belongs to a comment explaining the format.
+        let prose = commented && trimmed[1..].starts_with(char::is_whitespace);
+
+        let entry = if prose {
+            Entry::Other
+        } else if let Some(name) = body.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+            if is_valid_name(name) { Entry::Section(name.to_string()) } else { Entry::Other }
+        } else if let Some((key, value)) = body.split_once('=') {
+            let key = key.trim();
+            if is_key(key) { Entry::Option(key.to_string(), Some(value.trim().to_string())) } else { Entry::Other }
+        } else if is_key(body) {
+            Entry::Option(body.to_string(), None)
+        } else {
+            Entry::Other
+        };
+
+        Self {
+            text: text.to_string(),
+            commented,
+            entry,
+        }
+    }
+
+    fn section(&self) -> Option<&str> {
+        match &self.entry {
+            Entry::Section(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    fn option(&self) -> Option<(&str, Option<&str>)> {
+        match &self.entry {
+            Entry::Option(key, value) => Some((key, value.as_deref())),
+            _ => None,
+        }
+    }
+}
+
+fn is_key(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Repo {
+    pub name: String,
+    pub enabled: bool,
+    pub sig_level: Option<String>,
+    pub servers: Vec<String>,
+    // Set for repositories defined in a file pulled in with `Include`,
+    // those are shown but can't be edited here.
+    pub source: Option<PathBuf>,
+}
+
+// pacman.conf kept line by line, so saving only touches the lines that were
+// edited and every comment and blank line survives.
+#[derive(Debug, Clone, Default)]
+pub struct PacmanConf {
+    pub path: PathBuf,
+    lines: Vec<Line>,
+    trailing_newline: bool,
+}
+
+impl PacmanConf {
+    pub fn load() -> io::Result<Self> {
+        Self::load_from(Path::new(PATH))
+    }
+
+    pub fn load_from(path: &Path) -> io::Result<Self> {
+        let mut conf = Self::parse(&fs::read_to_string(path)?);
+        conf.path = path.to_path_buf();
+        Ok(conf)
+    }
+
+    pub fn parse(contents: &str) -> Self {
+        Self {
+            path: PathBuf::from(PATH),
+            lines: contents.lines().map(Line::parse).collect(),
+            trailing_newline: contents.ends_with('\n'),
+        }
+    }
+
+    pub fn contents(&self) -> String {
+        let mut contents = self.lines.iter().map(|line| line.text.as_str()).collect::<Vec<_>>().join("\n");
+        if self.trailing_newline {
+            contents.push('\n');
+        }
+        contents
+    }
+
+    // Index of the header of an active section, or of a commented one.
+    fn find_section(&self, name: &str, commented: bool) -> Option<usize> {
+        self.lines
+            .iter()
+            .position(|line| line.section() == Some(name) && line.commented == commented)
+    }
+
+    // Lines belonging to the section starting at `start`, header excluded.
+    // A commented section only owns the commented repository keys right below it.
+    fn section_body(&self, start: usize) -> std::ops::Range<usize> {
+        let commented = self.lines[start].commented;
+        let mut end = start + 1;
+        while end < self.lines.len() {
+            let line = &self.lines[end];
+            if line.section().is_some() && !line.commented {
+                break;
+            }
+            if commented {
+                let owned = line.commented
+                    && line.option().is_some_and(|(key, _)| REPO_KEYS.contains(&key));
+                if !owned {
+                    break;
+                }
+            } else if line.section().is_some() {
+                break;
+            }
+            end += 1;
+        }
+        start + 1..end
+    }
+
+    pub fn repos(&self) -> Vec<Repo> {
+        let mut repos = Vec::new();
+        for (index, line) in self.lines.iter().enumerate() {
+            let Some(name) = line.section() else {
+                continue;
+            };
+            if name == OPTIONS || (line.commented && self.find_section(name, false).is_some()) {
+                continue;
+            }
+            // A commented header without any settings is just a comment.
+            let body = self.section_body(index);
+            if line.commented && body.is_empty() {
+                continue;
+            }
+
+            let mut repo = Repo {
+                name: name.to_string(),
+                enabled: !line.commented,
+                ..Default::default()
+            };
+            for line in &self.lines[body] {
+                if line.commented != repo.enabled {
+                    self.read_repo_option(line, &mut repo);
+                }
+            }
+            repos.push(repo);
+        }
+
+        // Whole files of repositories can be pulled in from [options].
+        for include in self.option_values("Include") {
+            for path in resolve_include(&include) {
+                if let Ok(included) = Self::load_from(&path) {
+                    repos.extend(included.repos().into_iter().map(|mut repo| {
+                        repo.source.get_or_insert_with(|| path.clone());
+                        repo
+                    }));
+                }
+            }
+        }
+        repos
+    }
+
+    fn read_repo_option(&self, line: &Line, repo: &mut Repo) {
+        match line.option() {
+            Some(("SigLevel", Some(value))) => repo.sig_level = Some(value.to_string()),
+            Some(("Server", Some(value))) => repo.servers.push(value.to_string()),
+            Some(("Include", Some(value))) => {
+                for path in resolve_include(value) {
+                    repo.servers.extend(servers_in(&path));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn set_repo_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        let Some(start) = self.find_section(name, enabled) else {
+            return false;
+        };
+        let range = self.section_body(start);
+        for index in std::iter::once(start).chain(range) {
+            let line = &self.lines[index];
+            // Lines already commented inside an active section stay as they are.
+            if line.commented != enabled || (line.option().is_none() && line.section().is_none()) {
+                continue;
+            }
+            let text = if enabled { uncomment(&line.text) } else { format!("#{}", line.text) };
+            self.lines[index] = Line::parse(&text);
+        }
+        true
+    }
+
+    pub fn add_repo(&mut self, name: &str, sig_level: &str, server: &str) -> Result<(), String> {
+        if !is_valid_name(name) || name == OPTIONS {
+            return Err(format!("\"{}\" is not a valid repository name", name));
+        }
+        if self.find_section(name, false).is_some() || self.find_section(name, true).is_some() {
+            return Err(format!("A repository called \"{}\" already exists", name));
+        }
+        if server.trim().is_empty() {
+            return Err("The server address is missing".to_string());
+        }
+
+        if self.lines.last().is_some_and(|line| !line.text.trim().is_empty()) {
+            self.lines.push(Line::parse(""));
+        }
+        self.lines.push(Line::parse(&format!("[{}]", name)));
+        if !sig_level.trim().is_empty() {
+            self.lines.push(Line::parse(&format!("SigLevel = {}", sig_level.trim())));
+        }
+        self.lines.push(Line::parse(&format!("Server = {}", server.trim())));
+        self.trailing_newline = true;
+        Ok(())
+    }
+
+    fn option_values(&self, key: &str) -> Vec<String> {
+        let Some(start) = self.find_section(OPTIONS, false) else {
+            return Vec::new();
+        };
+        self.lines[self.section_body(start)]
+            .iter()
+            .filter(|line| !line.commented)
+            .filter_map(|line| match line.option() {
+                Some((name, Some(value))) if name == key => Some(value.to_string()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // The value of an [options] entry, Some("") for a flag that is set.
+    pub fn option(&self, key: &str) -> Option<String> {
+        let start = self.find_section(OPTIONS, false)?;
+        self.lines[self.section_body(start)]
+            .iter()
+            .filter(|line| !line.commented)
+            .find_map(|line| match line.option() {
+                Some((name, value)) if name == key => Some(value.unwrap_or("").to_string()),
+                _ => None,
+            })
+    }
+
+    // Sets an [options] entry in place, reusing a commented example line when
+    // there is one. Some("") sets a flag, None comments the entry out.
+    pub fn set_option(&mut self, key: &str, value: Option<&str>) {
+        let Some(start) = self.find_section(OPTIONS, false) else {
+            return;
+        };
+        let body = self.section_body(start);
+        let text = value.map(|value| if value.is_empty() { key.to_string() } else { format!("{} = {}", key, value) });
+
+        let matching = |commented: bool| {
+            body.clone().find(|&index| {
+                let line = &self.lines[index];
+                line.commented == commented && line.option().is_some_and(|(name, _)| name == key)
+            })
+        };
+
+        match (matching(false), text) {
+            (Some(index), Some(text)) => self.lines[index] = Line::parse(&text),
+            (Some(index), None) => {
+                let commented = format!("#{}", self.lines[index].text);
+                self.lines[index] = Line::parse(&commented);
+            }
+            (None, Some(text)) => match matching(true) {
+                Some(index) => self.lines[index] = Line::parse(&text),
+                None => {
+                    // Right after the last non blank line of [options].
+                    let index = body
+                        .clone()
+                        .rev()
+                        .find(|&index| !self.lines[index].text.trim().is_empty())
+                        .unwrap_or(start)
+                        + 1;
+                    self.lines.insert(index, Line::parse(&text));
+                }
+            },
+            (None, None) => {}
+        }
+    }
+}
+
+fn uncomment(text: &str) -> String {
+    let trimmed = text.trim_start();
+    trimmed.strip_prefix('#').unwrap_or(trimmed).trim_start().to_string()
+}
+
+// `Include` accepts globs, only `*` in the file name is supported here.
+fn resolve_include(pattern: &str) -> Vec<PathBuf> {
+    let path = Path::new(pattern);
+    let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+        return Vec::new();
+    };
+    if !file_name.contains('*') {
+        return vec![path.to_path_buf()];
+    }
+
+    let Some(dir) = path.parent() else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    path.file_name()
+                        .and_then(|name| name.to_str())
+                        .is_some_and(|name| auto_update::matches_pattern(file_name, name))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    paths.sort();
+    paths
+}
+
+// Active servers of a mirrorlist.
+fn servers_in(path: &Path) -> Vec<String> {
+    fs::read_to_string(path)
+        .map(|contents| {
+            contents
+                .lines()
+                .map(Line::parse)
+                .filter(|line| !line.commented)
+                .filter_map(|line| match line.option() {
+                    Some(("Server", Some(value))) => Some(value.to_string()),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Replaces the system file through pkexec, keeping the previous version next
+// to it as pacman.conf.bak. The contents go to the privileged side on stdin,
+// which writes, checks and installs its own copy, so nothing an unprivileged
+// process can touch ends up as the system's pacman.conf.
+const INSTALL_SCRIPT: &str = "set -e
+tmp=$(mktemp \"$1.XXXXXX\")
+trap 'rm -f \"$tmp\"' EXIT
+cat > \"$tmp\"
+pacman-conf --config \"$tmp\" --repo-list > /dev/null
+cp -p \"$1\" \"$1.bak\"
+chmod 644 \"$tmp\"
+mv \"$tmp\" \"$1\"";
+
+pub fn save(conf: &PacmanConf) -> Result<(), String> {
+    // Checked unprivileged first as well, a broken file shouldn't cost a
+    // password prompt.
+    let check = pipe(runner::command("pacman-conf").args(["--config", "/dev/stdin", "--repo-list"]), conf)
+        .map_err(|e| e.to_string())?;
+    if !check.status.success() {
+        return Err(String::from_utf8_lossy(&check.stderr).trim().to_string());
+    }
+
+    let output = pipe(
+        runner::command("pkexec")
+            .args(["sh", "-c", INSTALL_SCRIPT, "sh"])
+            .arg(&conf.path),
+        conf,
+    );
+
+    match output {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(output) => {
+            eprintln!("Command ran but failed with exit code: {}", output.status);
+            Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+        }
+        Err(e) => {
+            eprintln!("Failed to even launch pkexec: {}", e);
+            Err(e.to_string())
+        }
+    }
+}
+
+fn pipe(command: &mut Command, conf: &PacmanConf) -> io::Result<Output> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(conf.contents().as_bytes())?;
+    }
+    child.wait_with_output()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // /etc/pacman.conf as pacman 7.0 ships it, see tests/pacman-conf.
+    fn stock() -> String {
+        let path = format!("{}/tests/pacman-conf/pacman.conf", env!("CARGO_MANIFEST_DIR"));
+        fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path, e))
+    }
+
+    #[test]
+    fn round_trip_is_byte_identical() {
+        let stock = stock();
+        assert_eq!(PacmanConf::parse(&stock).contents(), stock);
+        assert_eq!(PacmanConf::parse("[options]\nColor").contents(), "[options]\nColor");
+    }
+
+    #[test]
+    fn lists_repositories() {
+        let repos = PacmanConf::parse(&stock()).repos();
+        let names: Vec<(&str, bool)> = repos.iter().map(|repo| (repo.name.as_str(), repo.enabled)).collect();
+        assert_eq!(names, [
+            ("core-testing", false),
+            ("core", true),
+            ("extra-testing", false),
+            ("extra", true),
+            ("multilib-testing", false),
+            ("multilib", false),
+            ("custom", false),
+        ]);
+        let custom = repos.last().unwrap();
+        assert_eq!(custom.sig_level.as_deref(), Some("Optional TrustAll"));
+        assert_eq!(custom.servers, ["file:///home/custompkgs"]);
+    }
+
+    #[test]
+    fn enabling_uncomments_header_and_include() {
+        let stock = stock();
+        let mut conf = PacmanConf::parse(&stock);
+        assert!(conf.set_repo_enabled("multilib", true));
+        assert_eq!(
+            conf.contents(),
+            stock.replace(
+                "#[multilib]\n#Include = /etc/pacman.d/mirrorlist\n",
+                "[multilib]\nInclude = /etc/pacman.d/mirrorlist\n",
+            ),
+        );
+
+        assert!(conf.set_repo_enabled("multilib", false));
+        assert_eq!(conf.contents(), stock);
+        assert!(!conf.set_repo_enabled("community", true));
+    }
+
+    #[test]
+    fn set_option_reuses_the_commented_example() {
+        let stock = stock();
+        let mut conf = PacmanConf::parse(&stock);
+        conf.set_option("ParallelDownloads", Some("5"));
+        assert_eq!(conf.contents(), stock.replace("#ParallelDownloads = 5\n", "ParallelDownloads = 5\n"));
+        assert_eq!(conf.option("ParallelDownloads").as_deref(), Some("5"));
+
+        conf.set_option("ParallelDownloads", None);
+        assert_eq!(conf.contents(), stock);
+        assert_eq!(conf.option("ParallelDownloads"), None);
+
+        conf.set_option("Color", Some(""));
+        assert_eq!(conf.option("Color").as_deref(), Some(""));
+        assert!(conf.contents().contains("\nColor\n#NoProgressBar\n"));
+    }
+
+    #[test]
+    fn add_repo_appends_a_section() {
+        let stock = stock();
+        let mut conf = PacmanConf::parse(&stock);
+        conf.add_repo("mine", "Optional TrustAll", " https://example.org/$repo/$arch ").unwrap();
+        assert_eq!(
+            conf.contents(),
+            format!("{}\n[mine]\nSigLevel = Optional TrustAll\nServer = https://example.org/$repo/$arch\n", stock),
+        );
+        let mine = conf.repos().pop().unwrap();
+        assert_eq!(mine, Repo {
+            name: "mine".to_string(),
+            enabled: true,
+            sig_level: Some("Optional TrustAll".to_string()),
+            servers: vec!["https://example.org/$repo/$arch".to_string()],
+            source: None,
+        });
+    }
+
+    #[test]
+    fn add_repo_refuses_bad_input() {
+        let mut conf = PacmanConf::parse(&stock());
+        assert!(conf.add_repo("custom", "", "https://example.org").is_err());
+        assert!(conf.add_repo("core", "", "https://example.org").is_err());
+        assert!(conf.add_repo("options", "", "https://example.org").is_err());
+        assert!(conf.add_repo("my repo", "", "https://example.org").is_err());
+        assert!(conf.add_repo("mine", "", " ").is_err());
+        assert_eq!(conf.contents(), stock());
+    }
+}
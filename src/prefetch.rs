@@ -0,0 +1,125 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use gtk::glib;
+
+use crate::pacman;
+use crate::runner;
+
+pub const SYSTEM_CACHE: &str = "/var/cache/pacman/pkg";
+// Smaller downloads are mostly connection setup, they say little about the
+// mirror's speed.
+const MIN_THROUGHPUT_SAMPLE: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Default)]
+pub struct PrefetchStatus {
+    pub downloaded: HashSet<String>,
+    pub bytes: u64,
+    // Bytes per second of this download, None if nothing much was fetched.
+    pub throughput: Option<u64>,
+}
+
+// Packages are fetched as the user into our own cache, the upgrade then
+// passes it as an extra --cachedir so pacman picks the files up from there.
+pub fn cache_dir() -> PathBuf {
+    glib::user_cache_dir().join("updater-new").join("pkg")
+}
+
+pub fn cachedir_args() -> Vec<String> {
+    vec![
+        "--cachedir".to_string(),
+        SYSTEM_CACHE.to_string(),
+        "--cachedir".to_string(),
+        cache_dir().to_string_lossy().to_string(),
+    ]
+}
+
+// Downloads the pending upgrades using the throwaway database checkupdates
+// synced, so neither root nor the system databases are touched.
+pub fn download() -> PrefetchStatus {
+    let Some(dbpath) = pacman::checkupdates_dbpath() else {
+        eprintln!("No checkupdates database to download from");
+        return PrefetchStatus::default();
+    };
+    let cache = cache_dir();
+    if let Err(e) = fs::create_dir_all(&cache) {
+        eprintln!("Failed to create {}: {}", cache.display(), e);
+        return PrefetchStatus::default();
+    }
+
+    let before = status().bytes;
+    let started = Instant::now();
+    match runner::command("fakeroot")
+        .args(["--", "pacman", "-Suw", "--noconfirm", "--noprogressbar", "--logfile", "/dev/null"])
+        .args(["--dbpath", &dbpath])
+        .arg("--cachedir")
+        .arg(&cache)
+        .output()
+    {
+        Ok(output) => {
+            if !output.status.success() {
+                eprintln!("Pre-download failed with exit code: {}", output.status);
+                eprintln!("Stderr: {}", String::from_utf8_lossy(&output.stderr));
+            }
+        }
+        Err(e) => eprintln!("Failed to launch fakeroot: {}", e),
+    }
+
+    let mut status = status();
+    status.throughput = throughput(status.bytes.saturating_sub(before), started.elapsed());
+    status
+}
+
+// Bytes per second, also for the downloads of regular transactions. None when
+// too little was fetched to tell.
+pub fn throughput(bytes: u64, time: Duration) -> Option<u64> {
+    let seconds = time.as_secs_f64();
+    (bytes >= MIN_THROUGHPUT_SAMPLE && seconds > 0.0).then(|| (bytes as f64 / seconds) as u64)
+}
+
+// Works out which pending packages already sit in one of the caches, and
+// drops files from our cache that no longer belong to a pending upgrade.
+pub fn status() -> PrefetchStatus {
+    let mut status = PrefetchStatus::default();
+    let Some(dbpath) = pacman::checkupdates_dbpath() else {
+        return status;
+    };
+
+    let Ok(output) = pacman::query(&["-Sup", "--dbpath", &dbpath, "--print-format", "%n %l"]) else {
+        return status;
+    };
+
+    let cache = cache_dir();
+    let mut wanted = HashSet::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let Some((name, location)) = line.split_once(' ') else {
+            continue;
+        };
+        let Some(file) = location.rsplit('/').next() else {
+            continue;
+        };
+        wanted.insert(file.to_string());
+
+        let user_file = cache.join(file);
+        if let Ok(metadata) = fs::metadata(&user_file) {
+            status.bytes += metadata.len();
+            status.downloaded.insert(name.to_string());
+        } else if Path::new(SYSTEM_CACHE).join(file).exists() {
+            status.downloaded.insert(name.to_string());
+        }
+    }
+
+    if let Ok(entries) = fs::read_dir(&cache) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let package = name.strip_suffix(".sig").unwrap_or(&name);
+            if !wanted.contains(package) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+
+    status
+}
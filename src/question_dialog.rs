@@ -0,0 +1,54 @@
+use adw::prelude::*;
+use gtk::glib;
+use crate::transaction::Question;
+use std::sync::mpsc;
+
+// Asks one of pacman's questions and sends back the line to type. Closing
+// the dialog keeps pacman's default answer.
+pub fn present(parent: &impl IsA<gtk::Widget>, question: Question, reply: mpsc::Sender<String>) {
+    let dialog = adw::AlertDialog::builder()
+        .close_response("default")
+        .build();
+
+    match &question {
+        Question::Confirm { text, default } => {
+            let heading = if text.starts_with("Replace ") {
+                "Replace Package?"
+            } else if text.contains("are in conflict") {
+                "Remove Conflicting Package?"
+            } else if text.starts_with("Import PGP key") {
+                "Import Signing Key?"
+            } else {
+                "Continue?"
+            };
+            dialog.set_heading(Some(heading));
+            dialog.set_body(text);
+            dialog.add_response("n", "No");
+            dialog.add_response("y", "Yes");
+            if text.contains("are in conflict") {
+                dialog.set_response_appearance("y", adw::ResponseAppearance::Destructive);
+            } else {
+                dialog.set_response_appearance("y", adw::ResponseAppearance::Suggested);
+            }
+            dialog.set_default_response(Some(if *default { "y" } else { "n" }));
+        }
+        Question::Choose { text, options, default } => {
+            dialog.set_heading(Some("Choose a Provider"));
+            dialog.set_body(text);
+            for (index, option) in options.iter().enumerate() {
+                dialog.add_response(&(index + 1).to_string(), option);
+            }
+            let default = (default + 1).to_string();
+            dialog.set_response_appearance(&default, adw::ResponseAppearance::Suggested);
+            dialog.set_default_response(Some(&default));
+        }
+    }
+
+    let default = question.default_answer();
+    dialog.connect_response(None, glib::clone!(#[strong] reply, move |_, response| {
+        let answer = if response == "default" { default.clone() } else { response.to_string() };
+        let _ = reply.send(answer);
+    }));
+
+    dialog.present(Some(parent));
+}
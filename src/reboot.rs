@@ -0,0 +1,185 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use crate::runner;
+
+// User units that make up the graphical session. Restarting one of them ends
+// the session, so they are only reported, like needrestart does for display
+// managers.
+const SESSION_UNITS: [&str; 13] = [
+    "org.gnome.Shell",
+    "org.gnome.SettingsDaemon.",
+    "gnome-session",
+    "gnome-shell",
+    "plasma-",
+    "kwin",
+    "xdg-desktop-portal",
+    "dbus",
+    "sway",
+    "hyprland",
+    "wayfire",
+    "graphical-session",
+    "xorg",
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct RebootStatus {
+    // Release of the running kernel when its modules are no longer installed.
+    pub outdated_kernel: Option<String>,
+    // User services that are safe to restart.
+    pub user_services: Vec<String>,
+    // Parts of the desktop session, they pick up the new libraries on the
+    // next login.
+    pub session_services: Vec<String>,
+    // Processes outside any service that still use replaced libraries.
+    pub processes: Vec<String>,
+    // Processes of other users, system services included, could not be
+    // read. They may use replaced libraries too, only a restart is sure.
+    pub system_unchecked: bool,
+}
+
+impl RebootStatus {
+    pub fn reboot_required(&self) -> bool {
+        self.outdated_kernel.is_some()
+    }
+
+    pub fn restart_required(&self) -> bool {
+        !self.user_services.is_empty()
+    }
+
+    pub fn is_clean(&self) -> bool {
+        !self.reboot_required()
+            && !self.restart_required()
+            && self.session_services.is_empty()
+            && self.processes.is_empty()
+    }
+}
+
+pub fn analyze() -> RebootStatus {
+    let scan = stale_processes();
+    RebootStatus {
+        outdated_kernel: outdated_kernel(),
+        user_services: scan.user_services.into_iter().collect(),
+        session_services: scan.session_services.into_iter().collect(),
+        processes: scan.processes.into_iter().collect(),
+        system_unchecked: scan.unreadable,
+    }
+}
+
+// A kernel upgrade removes the module directory of the running kernel.
+fn outdated_kernel() -> Option<String> {
+    let output = runner::command("uname").arg("-r").output().ok()?;
+    let release = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if release.is_empty() {
+        return None;
+    }
+
+    let modules = runner::modules_dir().join(&release);
+    if modules.join("modules.dep").exists() || modules.join("kernel").is_dir() {
+        None
+    } else {
+        Some(release)
+    }
+}
+
+#[derive(Default)]
+struct Scan {
+    user_services: BTreeSet<String>,
+    session_services: BTreeSet<String>,
+    processes: BTreeSet<String>,
+    unreadable: bool,
+}
+
+// Walks /proc looking for processes that still map a library which was
+// deleted or replaced on disk, like needrestart does. Without root only our
+// own processes are readable, system services are only noted as unchecked.
+fn stale_processes() -> Scan {
+    let mut scan = Scan::default();
+
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return scan;
+    };
+
+    for entry in entries.flatten() {
+        let pid = entry.file_name().to_string_lossy().to_string();
+        if !pid.chars().all(|c| c.is_ascii_digit()) {
+            continue;
+        }
+
+        let maps = match fs::read_to_string(entry.path().join("maps")) {
+            Ok(maps) => maps,
+            Err(e) => {
+                scan.unreadable |= e.kind() == std::io::ErrorKind::PermissionDenied;
+                continue;
+            }
+        };
+        if !maps.lines().any(maps_deleted_library) {
+            continue;
+        }
+
+        match service_of(&entry.path()) {
+            Some(service) if is_session_unit(&service) => {
+                scan.session_services.insert(service);
+            }
+            // Autostarted applications, restarting them would lose their state.
+            Some(service) if !service.starts_with("app-") => {
+                scan.user_services.insert(service);
+            }
+            _ => {
+                let name = fs::read_to_string(entry.path().join("comm")).unwrap_or(pid);
+                scan.processes.insert(name.trim().to_string());
+            }
+        }
+    }
+    scan
+}
+
+fn is_session_unit(service: &str) -> bool {
+    SESSION_UNITS.iter().any(|prefix| service.starts_with(prefix))
+}
+
+fn maps_deleted_library(line: &str) -> bool {
+    let Some(path) = line.split_whitespace().nth(5) else {
+        return false;
+    };
+    line.ends_with("(deleted)")
+        && (path.starts_with("/usr/lib") || path.starts_with("/usr/bin"))
+        && (path.contains(".so") || path.starts_with("/usr/bin"))
+}
+
+// Returns the user unit owning the process, our own processes all run in
+// the user manager.
+fn service_of(proc_dir: &Path) -> Option<String> {
+    let cgroup = fs::read_to_string(proc_dir.join("cgroup")).ok()?;
+    let path = cgroup.lines().next()?.rsplit(':').next()?;
+    if !path.contains("/user@") {
+        return None;
+    }
+    let service = path
+        .split('/')
+        .rev()
+        .find(|part| part.ends_with(".service") && !part.starts_with("user@"))?;
+    Some(service.to_string())
+}
+
+pub fn restart_services(user: &[String]) -> bool {
+    // Checked again, the list came from the UI.
+    let services: Vec<&String> = user.iter().filter(|service| !is_session_unit(service)).collect();
+    if services.is_empty() {
+        return true;
+    }
+    match runner::command("systemctl").args(["--user", "restart"]).args(services).status() {
+        Ok(status) => status.success(),
+        Err(e) => {
+            eprintln!("Failed to restart user services: {}", e);
+            false
+        }
+    }
+}
+
+pub fn reboot() {
+    if let Err(e) = runner::command("systemctl").arg("reboot").spawn() {
+        eprintln!("Failed to reboot: {}", e);
+    }
+}
@@ -0,0 +1,359 @@
+use gtk::glib;
+use std::fs;
+use std::sync::mpsc;
+
+use crate::pacman::{self, PendingUpdate};
+use crate::pacman_conf::PacmanConf;
+use crate::update_manager::UpdateManager;
+
+pub const CLI_ARG: &str = "--report";
+const LOG_FILE: &str = "/var/log/pacman.log";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Json,
+    Markdown,
+    Text,
+}
+
+impl ReportFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(ReportFormat::Json),
+            "markdown" | "md" => Some(ReportFormat::Markdown),
+            "text" | "txt" => Some(ReportFormat::Text),
+            _ => None,
+        }
+    }
+
+    // Picks the format from the extension of the file the report goes to.
+    pub fn from_path(path: &std::path::Path) -> Self {
+        path.extension()
+            .and_then(|extension| Self::from_name(&extension.to_string_lossy().to_lowercase()))
+            .unwrap_or(ReportFormat::Text)
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ReportFormat::Json => "json",
+            ReportFormat::Markdown => "md",
+            ReportFormat::Text => "txt",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportKind {
+    Pending,
+    Transaction,
+}
+
+impl ReportKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "pending" => Some(ReportKind::Pending),
+            "transaction" => Some(ReportKind::Transaction),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ReportKind::Pending => "pending",
+            ReportKind::Transaction => "transaction",
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            ReportKind::Pending => "Pending updates",
+            ReportKind::Transaction => "Last transaction",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReportEntry {
+    pub name: String,
+    pub old_version: String,
+    pub new_version: String,
+    pub repo: String,
+    // "pending", or what pacman logged for the package: "upgraded", "installed", ...
+    pub result: String,
+}
+
+// What was, or is about to be, changed on this machine, for change management.
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub kind: ReportKind,
+    pub host: String,
+    // ISO 8601, when the list was taken or the transaction started.
+    pub date: String,
+    // "pending", "completed", "failed" or "interrupted".
+    pub result: String,
+    pub entries: Vec<ReportEntry>,
+}
+
+impl Report {
+    pub fn pending(updates: &[PendingUpdate]) -> Self {
+        Self {
+            kind: ReportKind::Pending,
+            host: glib::host_name().to_string(),
+            date: glib::DateTime::now_local()
+                .ok()
+                .and_then(|now| now.format_iso8601().ok())
+                .map(|date| date.to_string())
+                .unwrap_or_default(),
+            result: "pending".to_string(),
+            entries: updates
+                .iter()
+                .map(|update| ReportEntry {
+                    name: update.name.clone(),
+                    old_version: update.old_version.clone(),
+                    new_version: update.new_version.clone(),
+                    repo: update.repo.clone(),
+                    result: "pending".to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    // The last transaction in pacman's log, whoever ran it.
+    pub fn last_transaction() -> Option<Self> {
+        let mut report = parse_transaction(&read_log()?)?;
+
+        // The log doesn't name repositories, the sync databases still know them.
+        let names: Vec<&str> = report.entries.iter().map(|entry| entry.name.as_str()).collect();
+        let sync = pacman::sync_info(&names);
+        for entry in report.entries.iter_mut() {
+            if let Some(info) = sync.get(&entry.name) {
+                entry.repo = info.repo.clone();
+            }
+        }
+        Some(report)
+    }
+
+    // e.g. "workstation-pending-2024-05-01.json"
+    pub fn file_name(&self, format: ReportFormat) -> String {
+        let day = self.date.get(..10).unwrap_or("report");
+        format!("{}-{}-{}.{}", self.host, self.kind.name(), day, format.extension())
+    }
+
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Json => self.to_json(),
+            ReportFormat::Markdown => self.to_markdown(),
+            ReportFormat::Text => self.to_text(),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let entries: Vec<String> = self.entries
+            .iter()
+            .map(|entry| format!(
+                "    {{\"name\": {}, \"old_version\": {}, \"new_version\": {}, \"repo\": {}, \"result\": {}}}",
+                json_string(&entry.name),
+                json_string(&entry.old_version),
+                json_string(&entry.new_version),
+                json_string(&entry.repo),
+                json_string(&entry.result)))
+            .collect();
+        format!(
+            "{{\n  \"kind\": {},\n  \"host\": {},\n  \"date\": {},\n  \"result\": {},\n  \"packages\": [\n{}\n  ]\n}}\n",
+            json_string(self.kind.name()),
+            json_string(&self.host),
+            json_string(&self.date),
+            json_string(&self.result),
+            entries.join(",\n"))
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut text = format!(
+            "# {} on {}\n\n- Date: {}\n- Result: {}\n- Packages: {}\n\n",
+            self.kind.title(), self.host, self.date, self.result, self.entries.len());
+        if self.entries.is_empty() {
+            return text;
+        }
+        text.push_str("| Package | Old version | New version | Repository | Result |\n");
+        text.push_str("|---|---|---|---|---|\n");
+        for entry in &self.entries {
+            text.push_str(&format!(
+                "| {} | {} | {} | {} | {} |\n",
+                entry.name, entry.old_version, entry.new_version, entry.repo, entry.result));
+        }
+        text
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = format!(
+            "{} on {}\nDate: {}\nResult: {}\nPackages: {}\n",
+            self.kind.title(), self.host, self.date, self.result, self.entries.len());
+        if !self.entries.is_empty() {
+            text.push('\n');
+        }
+        for entry in &self.entries {
+            let versions = match (entry.old_version.is_empty(), entry.new_version.is_empty()) {
+                (false, false) => format!("{} -> {}", entry.old_version, entry.new_version),
+                (true, _) => entry.new_version.clone(),
+                (false, true) => entry.old_version.clone(),
+            };
+            let repo = if entry.repo.is_empty() { String::new() } else { format!(" [{}]", entry.repo) };
+            text.push_str(&format!("{} {}{} {}\n", entry.name, versions, repo, entry.result));
+        }
+        text
+    }
+}
+
+// When pacman last started a full system upgrade as unix seconds, whoever ran
+// it, this app or a terminal.
+pub fn last_full_upgrade() -> Option<i64> {
+    parse_last_full_upgrade(&read_log()?)
+}
+
+fn read_log() -> Option<String> {
+    let path = PacmanConf::load()
+        .ok()
+        .and_then(|conf| conf.option("LogFile"))
+        .unwrap_or_else(|| LOG_FILE.to_string());
+    fs::read_to_string(&path)
+        .map_err(|e| eprintln!("Failed to read {}: {}", path, e))
+        .ok()
+}
+
+// "[2024-05-01T10:00:00+0200] [PACMAN] starting full system upgrade"
+fn parse_last_full_upgrade(log: &str) -> Option<i64> {
+    let line = log.lines().rev().find(|line| line.contains("[PACMAN] starting full system upgrade"))?;
+    let (date, _) = line.strip_prefix('[')?.split_once(']')?;
+    glib::DateTime::from_iso8601(date, None).ok().map(|date| date.to_unix())
+}
+
+// Reads the last "transaction started" block, e.g.
+// "[2024-05-01T10:00:00+0200] [ALPM] upgraded linux (6.8.1-1 -> 6.8.2-1)".
+fn parse_transaction(log: &str) -> Option<Report> {
+    let lines: Vec<&str> = log.lines().collect();
+    let start = lines.iter().rposition(|line| line.contains("[ALPM] transaction started"))?;
+
+    let date = lines[start]
+        .strip_prefix('[')
+        .and_then(|line| line.split_once(']'))
+        .map(|(date, _)| date.to_string())
+        .unwrap_or_default();
+    let mut report = Report {
+        kind: ReportKind::Transaction,
+        host: glib::host_name().to_string(),
+        date,
+        // Nothing after the start means pacman never got to finish.
+        result: "interrupted".to_string(),
+        entries: Vec::new(),
+    };
+
+    for line in &lines[start + 1..] {
+        let Some((_, message)) = line.split_once("[ALPM] ") else {
+            continue;
+        };
+        match message.trim() {
+            "transaction completed" => {
+                report.result = "completed".to_string();
+                break;
+            }
+            "transaction failed" => {
+                report.result = "failed".to_string();
+                break;
+            }
+            message => {
+                if let Some(entry) = parse_package_line(message) {
+                    report.entries.push(entry);
+                }
+            }
+        }
+    }
+    Some(report)
+}
+
+// "upgraded foo (1.0-1 -> 1.1-1)", "installed bar (2.0-1)", "removed baz (1.0-1)"
+fn parse_package_line(message: &str) -> Option<ReportEntry> {
+    let (action, rest) = message.split_once(' ')?;
+    if !["upgraded", "downgraded", "installed", "reinstalled", "removed"].contains(&action) {
+        return None;
+    }
+    let (name, versions) = rest.split_once(' ')?;
+    let versions = versions.trim().strip_prefix('(')?.strip_suffix(')')?;
+    let (old_version, new_version) = match versions.split_once(" -> ") {
+        Some((old, new)) => (old.to_string(), new.to_string()),
+        None if action == "removed" => (versions.to_string(), String::new()),
+        None => (String::new(), versions.to_string()),
+    };
+    Some(ReportEntry {
+        name: name.to_string(),
+        old_version,
+        new_version,
+        repo: String::new(),
+        result: action.to_string(),
+    })
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+// `updater-new --report [pending|transaction] [--format json|markdown|text]`
+// prints a report to stdout, for scripts and configuration management.
+pub fn run_cli(args: &[String]) -> i32 {
+    let mut kind = ReportKind::Pending;
+    let mut format = ReportFormat::Text;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--format" {
+            match args.next().and_then(|name| ReportFormat::from_name(name)) {
+                Some(value) => format = value,
+                None => {
+                    eprintln!("--format takes json, markdown or text");
+                    return 2;
+                }
+            }
+        } else if let Some(value) = ReportKind::from_name(arg) {
+            kind = value;
+        } else {
+            eprintln!("Unknown report argument: {}", arg);
+            return 2;
+        }
+    }
+
+    let report = match kind {
+        ReportKind::Pending => {
+            let (sender, receiver) = mpsc::channel();
+            UpdateManager::check_updates(sender);
+            match receiver.recv() {
+                Ok(Ok(updates)) => Report::pending(&updates),
+                Ok(Err(e)) => {
+                    eprintln!("Failed to check for updates: {}", e);
+                    return 1;
+                }
+                Err(_) => return 1,
+            }
+        }
+        ReportKind::Transaction => match Report::last_transaction() {
+            Some(report) => report,
+            None => {
+                eprintln!("No transaction found in pacman's log");
+                return 1;
+            }
+        },
+    };
+
+    print!("{}", report.render(format));
+    0
+}
@@ -0,0 +1,293 @@
+use adw::prelude::*;
+use adw::subclass::prelude::*;
+use gtk::glib;
+use crate::pacman_conf::{self, PacmanConf};
+use crate::update_manager::UpdateManager;
+use std::cell::RefCell;
+
+const SIG_LEVELS: [&str; 4] = ["Default", "Required DatabaseOptional", "Optional TrustAll", "Never"];
+
+mod imp {
+    use super::*;
+
+    #[derive(Default)]
+    pub struct ReposDialog {
+        pub conf: RefCell<PacmanConf>,
+        pub repos_group: adw::PreferencesGroup,
+        pub repo_rows: RefCell<Vec<adw::ActionRow>>,
+        pub options_group: adw::PreferencesGroup,
+        pub parallel_row: adw::SpinRow,
+        pub color_row: adw::SwitchRow,
+        pub verbose_row: adw::SwitchRow,
+        pub check_space_row: adw::SwitchRow,
+        pub ignore_row: adw::EntryRow,
+        pub status: gtk::Label,
+        pub save_button: gtk::Button,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for ReposDialog {
+        const NAME: &'static str = "ReposDialog";
+        type Type = super::ReposDialog;
+        type ParentType = adw::Dialog;
+    }
+
+    impl ObjectImpl for ReposDialog {
+        fn constructed(&self) {
+            self.parent_constructed();
+            let obj = self.obj();
+            obj.setup_ui();
+            obj.load();
+        }
+    }
+
+    impl WidgetImpl for ReposDialog {}
+    impl AdwDialogImpl for ReposDialog {}
+}
+
+glib::wrapper! {
+    pub struct ReposDialog(ObjectSubclass<imp::ReposDialog>)
+        @extends gtk::Widget, adw::Dialog;
+}
+
+impl ReposDialog {
+    pub fn new() -> Self {
+        glib::Object::builder()
+            .property("title", "Repositories")
+            .property("content-width", 600)
+            .property("content-height", 640)
+            .build()
+    }
+
+    fn setup_ui(&self) {
+        let imp = self.imp();
+
+        imp.repos_group.set_title("Repositories");
+        imp.repos_group.set_description(Some(pacman_conf::PATH));
+        let add_button = gtk::Button::builder()
+            .icon_name("list-add-symbolic")
+            .tooltip_text("Add a custom repository")
+            .valign(gtk::Align::Center)
+            .css_classes(["flat"])
+            .build();
+        add_button.connect_clicked(glib::clone!(#[weak(rename_to = obj)] self, move |_| {
+            obj.show_add_repo();
+        }));
+        imp.repos_group.set_header_suffix(Some(&add_button));
+
+        imp.options_group.set_title("Options");
+
+        imp.parallel_row.set_title("Parallel Downloads");
+        imp.parallel_row.set_subtitle("Packages downloaded at the same time");
+        imp.parallel_row.set_adjustment(Some(&gtk::Adjustment::new(1.0, 1.0, 20.0, 1.0, 5.0, 0.0)));
+        imp.color_row.set_title("Color");
+        imp.color_row.set_subtitle("Colored pacman output");
+        imp.verbose_row.set_title("Verbose Package Lists");
+        imp.verbose_row.set_subtitle("Show versions in a table before transactions");
+        imp.check_space_row.set_title("Check Space");
+        imp.check_space_row.set_subtitle("Make sure there is enough disk space first");
+        imp.ignore_row.set_title("Ignored Packages");
+        imp.ignore_row.set_show_apply_button(true);
+
+        imp.options_group.add(&imp.parallel_row);
+        imp.options_group.add(&imp.color_row);
+        imp.options_group.add(&imp.verbose_row);
+        imp.options_group.add(&imp.check_space_row);
+        imp.options_group.add(&imp.ignore_row);
+
+        imp.parallel_row.connect_value_notify(glib::clone!(#[weak(rename_to = obj)] self, move |row| {
+            obj.edit(|conf| conf.set_option("ParallelDownloads", Some(&(row.value() as u32).to_string())));
+        }));
+        for (row, key) in [(&imp.color_row, "Color"), (&imp.verbose_row, "VerbosePkgLists"), (&imp.check_space_row, "CheckSpace")] {
+            row.connect_active_notify(glib::clone!(#[weak(rename_to = obj)] self, move |row| {
+                obj.edit(|conf| conf.set_option(key, row.is_active().then_some("")));
+            }));
+        }
+        imp.ignore_row.connect_apply(glib::clone!(#[weak(rename_to = obj)] self, move |row| {
+            let packages = row.text().split_whitespace().collect::<Vec<_>>().join(" ");
+            obj.edit(|conf| conf.set_option("IgnorePkg", (!packages.is_empty()).then_some(packages.as_str())));
+        }));
+
+        let page = adw::PreferencesPage::new();
+        page.add(&imp.repos_group);
+        page.add(&imp.options_group);
+
+        imp.status.set_halign(gtk::Align::Center);
+        imp.status.set_wrap(true);
+        imp.status.set_margin_bottom(12);
+
+        let content = gtk::Box::builder()
+            .orientation(gtk::Orientation::Vertical)
+            .build();
+        content.append(&page);
+        content.append(&imp.status);
+        page.set_vexpand(true);
+
+        imp.save_button.set_label("Save");
+        imp.save_button.add_css_class("suggested-action");
+        imp.save_button.set_sensitive(false);
+        imp.save_button.connect_clicked(glib::clone!(#[weak(rename_to = obj)] self, move |_| {
+            obj.save();
+        }));
+
+        let header = adw::HeaderBar::new();
+        header.pack_end(&imp.save_button);
+
+        let toolbar = adw::ToolbarView::new();
+        toolbar.add_top_bar(&header);
+        toolbar.set_content(Some(&content));
+        self.set_child(Some(&toolbar));
+    }
+
+    fn load(&self) {
+        let imp = self.imp();
+        match PacmanConf::load() {
+            Ok(conf) => {
+                imp.conf.replace(conf);
+            }
+            Err(e) => {
+                imp.status.set_text(&format!("Cannot read {}: {}", pacman_conf::PATH, e));
+                imp.options_group.set_sensitive(false);
+                imp.repos_group.set_sensitive(false);
+                return;
+            }
+        }
+
+        // Filling the option rows must not count as an edit.
+        let conf = imp.conf.borrow().clone();
+        let parallel = conf.option("ParallelDownloads").and_then(|value| value.parse::<f64>().ok());
+        imp.parallel_row.set_value(parallel.unwrap_or(1.0));
+        imp.color_row.set_active(conf.option("Color").is_some());
+        imp.verbose_row.set_active(conf.option("VerbosePkgLists").is_some());
+        imp.check_space_row.set_active(conf.option("CheckSpace").is_some());
+        imp.ignore_row.set_text(&conf.option("IgnorePkg").unwrap_or_default());
+        imp.conf.replace(conf);
+
+        self.show_repos();
+        imp.save_button.set_sensitive(false);
+        imp.status.set_text("");
+    }
+
+    fn show_repos(&self) {
+        let imp = self.imp();
+        for row in imp.repo_rows.borrow_mut().drain(..) {
+            imp.repos_group.remove(&row);
+        }
+
+        let repos = imp.conf.borrow().repos();
+        for repo in repos {
+            let mut details = vec![match repo.servers.len() {
+                1 => "1 server".to_string(),
+                count => format!("{} servers", count),
+            }];
+            if let Some(sig_level) = repo.sig_level.as_ref() {
+                details.push(sig_level.clone());
+            }
+            if let Some(source) = repo.source.as_ref() {
+                details.push(format!("from {}", source.display()));
+            }
+
+            let row = adw::ActionRow::builder()
+                .title(&repo.name)
+                .subtitle(details.join(" · "))
+                .build();
+            let switch = gtk::Switch::builder()
+                .valign(gtk::Align::Center)
+                .active(repo.enabled)
+                .sensitive(repo.source.is_none())
+                .build();
+            let name = repo.name.clone();
+            switch.connect_active_notify(glib::clone!(#[weak(rename_to = obj)] self, move |switch| {
+                let enabled = switch.is_active();
+                obj.edit(|conf| { conf.set_repo_enabled(&name, enabled); });
+            }));
+            row.add_suffix(&switch);
+            row.set_activatable_widget(Some(&switch));
+
+            imp.repos_group.add(&row);
+            imp.repo_rows.borrow_mut().push(row);
+        }
+    }
+
+    fn edit(&self, change: impl FnOnce(&mut PacmanConf)) {
+        let imp = self.imp();
+        change(&mut imp.conf.borrow_mut());
+        imp.save_button.set_sensitive(true);
+        imp.status.set_text("Unsaved changes");
+    }
+
+    fn show_add_repo(&self) {
+        let name_row = adw::EntryRow::builder().title("Name").build();
+        let server_row = adw::EntryRow::builder().title("Server").build();
+        let sig_level_row = adw::ComboRow::builder()
+            .title("Signature Level")
+            .model(&gtk::StringList::new(&SIG_LEVELS))
+            .build();
+
+        let list = gtk::ListBox::builder()
+            .selection_mode(gtk::SelectionMode::None)
+            .css_classes(["boxed-list"])
+            .build();
+        list.append(&name_row);
+        list.append(&server_row);
+        list.append(&sig_level_row);
+
+        let dialog = adw::AlertDialog::builder()
+            .heading("Add Repository")
+            .body("The server may use $repo and $arch, for example https://example.org/$repo/os/$arch")
+            .extra_child(&list)
+            .close_response("cancel")
+            .default_response("add")
+            .build();
+        dialog.add_response("cancel", "Cancel");
+        dialog.add_response("add", "Add");
+        dialog.set_response_appearance("add", adw::ResponseAppearance::Suggested);
+
+        dialog.connect_response(None, glib::clone!(#[weak(rename_to = obj)] self, move |_, response| {
+            if response != "add" {
+                return;
+            }
+            let sig_level = match sig_level_row.selected() {
+                0 => "",
+                index => SIG_LEVELS[index as usize],
+            };
+            let result = obj.imp().conf.borrow_mut().add_repo(&name_row.text(), sig_level, &server_row.text());
+            match result {
+                Ok(()) => {
+                    obj.show_repos();
+                    obj.imp().save_button.set_sensitive(true);
+                    obj.imp().status.set_text("Unsaved changes");
+                }
+                Err(error) => obj.imp().status.set_text(&error),
+            }
+        }));
+
+        dialog.present(Some(self));
+    }
+
+    fn save(&self) {
+        let imp = self.imp();
+        imp.save_button.set_sensitive(false);
+        imp.status.set_text("Saving...");
+
+        let (sender, receiver) = std::sync::mpsc::channel::<Result<(), String>>();
+        UpdateManager::save_pacman_conf(imp.conf.borrow().clone(), sender);
+
+        glib::timeout_add_local(std::time::Duration::from_millis(100),
+            glib::clone!(#[weak(rename_to = obj)] self, #[upgrade_or] glib::ControlFlow::Break, move || {
+                if let Ok(result) = receiver.try_recv() {
+                    match result {
+                        Ok(()) => obj.imp().status.set_text(&format!("Saved, the previous file is {}.bak", pacman_conf::PATH)),
+                        Err(error) => {
+                            obj.imp().status.set_text(&format!("Saving failed: {}", error));
+                            obj.imp().save_button.set_sensitive(true);
+                        }
+                    }
+                    glib::ControlFlow::Break
+                } else {
+                    glib::ControlFlow::Continue
+                }
+            })
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::OnceLock;
+
+// Points checkupdates, pacman and pkexec at a directory of stand-ins, see
+// tests/README.md.
+pub const FAKE_BIN_ENV: &str = "UPDATER_NEW_FAKE_BIN";
+// Stands in for /usr/lib/modules next to a fake `uname`, only read together
+// with FAKE_BIN_ENV.
+pub const FAKE_MODULES_ENV: &str = "UPDATER_NEW_FAKE_MODULES";
+
+// Where kernels install their modules, one directory per release.
+pub const MODULES: &str = "/usr/lib/modules";
+
+// Everything that reads or changes the system goes through a runner, so the
+// update flow can be driven without root or network.
+pub trait CommandRunner: Send + Sync {
+    fn command(&self, program: &str) -> Command;
+
+    // What the kernel check reads, it has to agree with `uname -r`.
+    fn modules_dir(&self) -> PathBuf {
+        PathBuf::from(MODULES)
+    }
+}
+
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn command(&self, program: &str) -> Command {
+        Command::new(program)
+    }
+}
+
+// Prefers executables from `dir`, anything missing there runs from $PATH.
+pub struct FakeBinRunner {
+    pub dir: PathBuf,
+    pub modules: Option<PathBuf>,
+}
+
+impl CommandRunner for FakeBinRunner {
+    fn command(&self, program: &str) -> Command {
+        let fake = self.dir.join(program);
+        if fake.is_file() {
+            Command::new(fake)
+        } else {
+            Command::new(program)
+        }
+    }
+
+    fn modules_dir(&self) -> PathBuf {
+        self.modules.clone().unwrap_or_else(|| PathBuf::from(MODULES))
+    }
+}
+
+static RUNNER: OnceLock<Box<dyn CommandRunner>> = OnceLock::new();
+
+// Has to happen before the first command runs, returns false otherwise.
+pub fn set_runner(runner: impl CommandRunner + 'static) -> bool {
+    RUNNER.set(Box::new(runner)).is_ok()
+}
+
+fn runner() -> &'static dyn CommandRunner {
+    RUNNER.get_or_init(|| Box::new(SystemRunner)).as_ref()
+}
+
+pub fn command(program: &str) -> Command {
+    runner().command(program)
+}
+
+pub fn modules_dir() -> PathBuf {
+    runner().modules_dir()
+}
@@ -0,0 +1,152 @@
+use gtk::glib;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::pacman::PendingUpdate;
+
+// Warns about a system that was not upgraded for this long.
+pub const STALE_UPGRADE_DAYS: i64 = 30;
+
+const DAY: i64 = 24 * 60 * 60;
+// The download estimate averages this many recent measurements.
+const THROUGHPUT_SAMPLES: usize = 5;
+
+// What the app remembers between runs, kept in $XDG_STATE_HOME as
+// "key=value" lines next to the auto update report. Times are unix seconds,
+// 0 means never.
+#[derive(Debug, Clone, Default)]
+pub struct StoredState {
+    pub last_check: i64,
+    pub last_upgrade: i64,
+    // The pending updates as of `last_check`.
+    pub pending: Vec<PendingUpdate>,
+    pub last_error: String,
+    pub last_error_time: i64,
+    // Mirror throughput of recent downloads in bytes per second, oldest first.
+    pub throughput: Vec<u64>,
+}
+
+impl StoredState {
+    pub fn load() -> Self {
+        fs::read_to_string(path())
+            .map(|contents| Self::parse(&contents))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        let path = path();
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        if let Err(e) = fs::write(&path, self.serialize()) {
+            eprintln!("Failed to write {}: {}", path.display(), e);
+        }
+    }
+
+    fn parse(contents: &str) -> Self {
+        let mut state = Self::default();
+        for line in contents.lines() {
+            match line.split_once('=') {
+                Some(("last-check", value)) => state.last_check = value.parse().unwrap_or(0),
+                Some(("last-upgrade", value)) => state.last_upgrade = value.parse().unwrap_or(0),
+                Some(("last-error", value)) => state.last_error = value.to_string(),
+                Some(("last-error-time", value)) => state.last_error_time = value.parse().unwrap_or(0),
+                Some(("pending", value)) => state.pending.extend(parse_pending(value)),
+                Some(("throughput", value)) => state.throughput.extend(value.parse::<u64>().ok()),
+                _ => {}
+            }
+        }
+        state
+    }
+
+    fn serialize(&self) -> String {
+        let mut contents = format!(
+            "last-check={}\nlast-upgrade={}\nlast-error={}\nlast-error-time={}\n",
+            self.last_check,
+            self.last_upgrade,
+            // One line per key, the first line of an error says enough.
+            self.last_error.lines().next().unwrap_or(""),
+            self.last_error_time,
+        );
+        for sample in &self.throughput {
+            contents.push_str(&format!("throughput={}\n", sample));
+        }
+        for update in &self.pending {
+            contents.push_str(&format!(
+                "pending={}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                update.name,
+                update.old_version,
+                update.new_version,
+                update.repo,
+                update.download_size,
+                update.installed_delta,
+                if update.security { 1 } else { 0 },
+            ));
+        }
+        contents
+    }
+
+    pub fn record_check(&mut self, pending: &[PendingUpdate]) {
+        self.last_check = now();
+        self.pending = pending.to_vec();
+    }
+
+    pub fn record_upgrade(&mut self, finished: i64) {
+        self.last_upgrade = self.last_upgrade.max(finished);
+        self.pending.clear();
+    }
+
+    pub fn record_error(&mut self, reason: &str) {
+        self.last_error = reason.to_string();
+        self.last_error_time = now();
+    }
+
+    pub fn record_throughput(&mut self, bytes_per_second: u64) {
+        self.throughput.push(bytes_per_second);
+        let excess = self.throughput.len().saturating_sub(THROUGHPUT_SAMPLES);
+        self.throughput.drain(..excess);
+    }
+
+    pub fn average_throughput(&self) -> Option<u64> {
+        (!self.throughput.is_empty())
+            .then(|| self.throughput.iter().sum::<u64>() / self.throughput.len() as u64)
+    }
+
+    // The last error, unless an upgrade went through since.
+    pub fn unresolved_error(&self) -> Option<&str> {
+        (!self.last_error.is_empty() && self.last_error_time > self.last_upgrade)
+            .then_some(self.last_error.as_str())
+    }
+
+    pub fn days_since_upgrade(&self) -> Option<i64> {
+        (self.last_upgrade > 0).then(|| (now() - self.last_upgrade) / DAY)
+    }
+
+    pub fn upgrade_is_stale(&self) -> bool {
+        self.days_since_upgrade().is_some_and(|days| days >= STALE_UPGRADE_DAYS)
+    }
+}
+
+fn parse_pending(value: &str) -> Option<PendingUpdate> {
+    let fields: Vec<&str> = value.split('\t').collect();
+    let [name, old_version, new_version, repo, download_size, installed_delta, security] = fields[..] else {
+        return None;
+    };
+    Some(PendingUpdate {
+        name: name.to_string(),
+        old_version: old_version.to_string(),
+        new_version: new_version.to_string(),
+        repo: repo.to_string(),
+        download_size: download_size.parse().unwrap_or(0),
+        installed_delta: installed_delta.parse().unwrap_or(0),
+        security: security == "1",
+    })
+}
+
+fn path() -> PathBuf {
+    glib::user_state_dir().join("updater-new").join("state")
+}
+
+fn now() -> i64 {
+    glib::real_time() / 1_000_000
+}
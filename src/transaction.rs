@@ -0,0 +1,294 @@
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Lines starting with this are messages from our own privileged helper
+// (`--alpm-transaction`) rather than pacman output.
+pub const MESSAGE_PREFIX: &str = "@updater ";
+
+// How long an unfinished line that looks like a question may sit there before
+// it counts as one nobody is going to answer.
+const PROMPT_TIMEOUT: Duration = Duration::from_secs(10);
+// Hooks such as DKMS builds can be quiet for a while, but not for this long.
+const STALL_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+// A question pacman asked on its terminal, in its own English wording.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Question {
+    // "Replace foo with extra/bar?", "foo and bar are in conflict. Remove bar?"
+    Confirm { text: String, default: bool },
+    // Which of several packages should provide a dependency.
+    Choose { text: String, options: Vec<String>, default: usize },
+}
+
+impl Question {
+    // What to type when the user closes the dialog without answering.
+    pub fn default_answer(&self) -> String {
+        match self {
+            Question::Confirm { default, .. } => if *default { "y" } else { "n" }.to_string(),
+            Question::Choose { default, .. } => (default + 1).to_string(),
+        }
+    }
+
+    // "confirm y <text>" or "choose <default> <text>\t<option>\t…", one line.
+    #[cfg(feature = "alpm")]
+    pub fn to_message(&self) -> String {
+        match self {
+            Question::Confirm { text, default } => {
+                format!("{}confirm {} {}", MESSAGE_PREFIX, if *default { "y" } else { "n" }, text)
+            }
+            Question::Choose { text, options, default } => {
+                format!("{}choose {} {}\t{}", MESSAGE_PREFIX, default, text, options.join("\t"))
+            }
+        }
+    }
+
+    fn from_message(message: &str) -> Option<Self> {
+        let (kind, rest) = message.split_once(' ')?;
+        let (default, text) = rest.split_once(' ')?;
+        match kind {
+            "confirm" => Some(Question::Confirm {
+                text: text.to_string(),
+                default: default == "y",
+            }),
+            "choose" => {
+                let mut parts = text.split('\t');
+                Some(Question::Choose {
+                    text: parts.next()?.to_string(),
+                    options: parts.map(str::to_string).collect(),
+                    default: default.parse().ok()?,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "alpm")]
+pub fn progress_message(percent: u32, text: &str) -> String {
+    format!("{}progress {} {}", MESSAGE_PREFIX, percent, text)
+}
+
+pub enum TransactionEvent {
+    // The worker waits on the sender for the line to type.
+    Question(Question, mpsc::Sender<String>),
+    // Percentage and what is being done, only sent by the libalpm backend.
+    Progress(u32, String),
+    // How long pacman spent retrieving packages, sent before Finished when
+    // it downloaded anything.
+    Downloaded(Duration),
+    // pacman's output, hooks included, either way.
+    Finished(Result<String, String>),
+}
+
+// Runs pacman without --noconfirm and relays its questions as events.
+// Output is merged into one stream because pacman prints questions on stderr
+// and the choices they refer to on stdout. A question that is not recognized
+// gets no answer: stdin is closed, pacman declines it and the transaction
+// fails. So does one that stops printing anything for STALL_TIMEOUT.
+pub fn run(command: Command, events: &mpsc::Sender<TransactionEvent>) -> Result<String, String> {
+    let mut wrapped = Command::new("sh");
+    wrapped
+        .args(["-c", "exec \"$@\" 2>&1", "sh"])
+        .arg(command.get_program())
+        .args(command.get_args())
+        .env("LC_ALL", "C")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+
+    let mut child = wrapped.spawn().map_err(|e| e.to_string())?;
+    let mut stdin = child.stdin.take();
+    let mut stdout = child.stdout.take().unwrap();
+
+    // Read on the side, so a silent pacman can be noticed.
+    let (chunks, received) = mpsc::channel::<Vec<u8>>();
+    thread::spawn(move || {
+        let mut buffer = [0u8; 4096];
+        loop {
+            match stdout.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => {
+                    if chunks.send(buffer[..read].to_vec()).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Failed to read pacman's output: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    let mut log = String::new();
+    let mut pending = String::new();
+    let mut unanswered = None;
+    let mut retrieving = None;
+    let mut download_time = None;
+    loop {
+        let timeout = if looks_like_prompt(&pending) { PROMPT_TIMEOUT } else { STALL_TIMEOUT };
+        let chunk = match received.recv_timeout(timeout) {
+            Ok(chunk) => chunk,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            Err(mpsc::RecvTimeoutError::Timeout) if timeout == PROMPT_TIMEOUT => {
+                eprintln!("Unanswered question: {}", pending.trim());
+                log.push_str(&format!("{}\n", pending));
+                unanswered = Some(pending.trim().trim_start_matches(":: ").to_string());
+                pending.clear();
+                stdin = None;
+                continue;
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                eprintln!("pacman printed nothing for {} minutes, no longer waiting for it", STALL_TIMEOUT.as_secs() / 60);
+                log.push_str(&pending);
+                log.push_str("error: pacman stopped responding\n");
+                // Running as root, it can't be killed from here.
+                thread::spawn(move || child.wait());
+                return Err(log);
+            }
+        };
+        pending.push_str(&String::from_utf8_lossy(&chunk));
+
+        while let Some(end) = pending.find('\n') {
+            let line: String = pending.drain(..=end).collect();
+            if let Some(message) = line.trim_end().strip_prefix(MESSAGE_PREFIX) {
+                if let Some(answer) = answer_message(message, events) {
+                    reply(&mut stdin, &answer);
+                }
+                continue;
+            }
+            if line.starts_with(":: Retrieving packages") {
+                retrieving = Some(Instant::now());
+            } else if let Some(since) = retrieving.filter(|_| download_done(&line)) {
+                download_time = Some(since.elapsed());
+                retrieving = None;
+            }
+            log.push_str(&line);
+        }
+
+        if let Some(answer) = answer_prompt(&pending, &log, events) {
+            log.push_str(&format!("{}{}\n", pending, answer));
+            pending.clear();
+            reply(&mut stdin, &answer);
+        }
+    }
+    log.push_str(&pending);
+
+    let status = child.wait();
+    if let Some(question) = unanswered {
+        log.push_str(&format!("error: unexpected question: {}\n", question));
+        return Err(log);
+    }
+    match status {
+        Ok(status) if status.success() => {
+            if let Some(time) = download_time {
+                let _ = events.send(TransactionEvent::Downloaded(time));
+            }
+            Ok(log)
+        }
+        Ok(status) => {
+            eprintln!("Transaction failed with exit code: {}", status);
+            Err(log)
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// Once writing fails pacman is gone, the rest of its output still gets read.
+fn reply(stdin: &mut Option<std::process::ChildStdin>, answer: &str) {
+    if let Some(pipe) = stdin {
+        if writeln!(pipe, "{}", answer).is_err() {
+            *stdin = None;
+        }
+    }
+}
+
+// The first step after the download, older pacman says "checking keyring...".
+fn download_done(line: &str) -> bool {
+    ["checking keys in keyring", "checking keyring", "checking package integrity"]
+        .iter()
+        .any(|step| line.contains(step))
+}
+
+// Prompts wait after a space, "[Y/n] " or ": ", the dots a slow hook
+// prints while it works don't.
+fn looks_like_prompt(unfinished: &str) -> bool {
+    unfinished.ends_with(' ')
+}
+
+fn answer_message(message: &str, events: &mpsc::Sender<TransactionEvent>) -> Option<String> {
+    if let Some(progress) = message.strip_prefix("progress ") {
+        let (percent, text) = progress.split_once(' ').unwrap_or((progress, ""));
+        let _ = events.send(TransactionEvent::Progress(percent.parse().unwrap_or(0), text.to_string()));
+        return None;
+    }
+    Some(ask(Question::from_message(message)?, events))
+}
+
+fn ask(question: Question, events: &mpsc::Sender<TransactionEvent>) -> String {
+    let (sender, receiver) = mpsc::channel::<String>();
+    let default = question.default_answer();
+    if events.send(TransactionEvent::Question(question, sender)).is_err() {
+        return default;
+    }
+    receiver.recv().unwrap_or(default)
+}
+
+// The answer for an unfinished line, when it is one of pacman's prompts.
+fn answer_prompt(prompt: &str, log: &str, events: &mpsc::Sender<TransactionEvent>) -> Option<String> {
+    let question = if let Some(text) = prompt.strip_suffix("[Y/n] ").or_else(|| prompt.strip_suffix("[y/N] ")) {
+        let text = text.trim().trim_start_matches(":: ").to_string();
+        // The user already asked for the update by pressing the button.
+        if text.starts_with("Proceed with") {
+            return Some("y".to_string());
+        }
+        Question::Confirm {
+            default: prompt.ends_with("[Y/n] "),
+            text,
+        }
+    } else if prompt.starts_with("Enter a selection") && prompt.ends_with(": ") {
+        // Members of a group, installing all of them is what was asked for.
+        return Some(String::new());
+    } else if let Some(rest) = prompt.strip_prefix("Enter a number (default=") {
+        if !prompt.ends_with(": ") {
+            return None;
+        }
+        let default = rest.split(')').next()?.parse::<usize>().ok()?.saturating_sub(1);
+        let (text, options) = providers(log);
+        Question::Choose { text, options, default }
+    } else {
+        return None;
+    };
+
+    Some(ask(question, events))
+}
+
+// ":: There are 2 providers available for foo:" followed by lines such as
+// "   1) bar  2) baz", possibly split per repository.
+fn providers(log: &str) -> (String, Vec<String>) {
+    let lines: Vec<&str> = log.lines().collect();
+    let start = lines
+        .iter()
+        .rposition(|line| line.contains("provider") && line.contains("available for"))
+        .unwrap_or(0);
+
+    let mut options = Vec::new();
+    for line in &lines[start + 1..] {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        for pair in tokens.windows(2) {
+            let is_number = pair[0].strip_suffix(')').is_some_and(|number| number.parse::<usize>().is_ok());
+            if is_number {
+                options.push(pair[1].to_string());
+            }
+        }
+    }
+
+    let text = lines
+        .get(start)
+        .map(|line| line.trim().trim_start_matches(":: ").trim_end_matches(':').to_string())
+        .unwrap_or_default();
+    (text, options)
+}
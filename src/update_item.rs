@@ -0,0 +1,143 @@
+use gtk::glib;
+use gtk::subclass::prelude::*;
+use crate::pacman::{self, PendingUpdate};
+use std::cell::{Cell, RefCell};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, glib::Enum)]
+#[enum_type(name = "UpdateSeverity")]
+pub enum Severity {
+    #[default]
+    Normal,
+    KernelDriver,
+    Security,
+}
+
+mod imp {
+    use super::*;
+    use glib::prelude::*;
+
+    #[derive(Default, glib::Properties)]
+    #[properties(wrapper_type = super::UpdateItem)]
+    pub struct UpdateItem {
+        #[property(get, set)]
+        pub name: RefCell<String>,
+        #[property(get, set)]
+        pub old_version: RefCell<String>,
+        #[property(get, set)]
+        pub new_version: RefCell<String>,
+        #[property(get, set)]
+        pub repo: RefCell<String>,
+        #[property(get, set)]
+        pub download_size: Cell<u64>,
+        #[property(get, set)]
+        pub installed_delta: Cell<i64>,
+        #[property(get, set, builder(Severity::Normal))]
+        pub severity: Cell<Severity>,
+        #[property(get, set)]
+        pub downloaded: Cell<bool>,
+        #[property(get, set)]
+        pub held: Cell<bool>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for UpdateItem {
+        const NAME: &'static str = "UpdateItem";
+        type Type = super::UpdateItem;
+    }
+
+    #[glib::derived_properties]
+    impl ObjectImpl for UpdateItem {}
+}
+
+glib::wrapper! {
+    pub struct UpdateItem(ObjectSubclass<imp::UpdateItem>);
+}
+
+impl UpdateItem {
+    pub fn new(update: &PendingUpdate) -> Self {
+        glib::Object::builder()
+            .property("name", &update.name)
+            .property("old-version", &update.old_version)
+            .property("new-version", &update.new_version)
+            .property("repo", &update.repo)
+            .property("download-size", update.download_size)
+            .property("installed-delta", update.installed_delta)
+            .property("severity", severity(update))
+            .build()
+    }
+
+    // For an item kept across checks, the versions are the same but the
+    // repository, the sizes and the advisories may have changed.
+    pub fn refresh(&self, update: &PendingUpdate) {
+        self.set_repo(update.repo.as_str());
+        self.set_download_size(update.download_size);
+        self.set_installed_delta(update.installed_delta);
+        self.set_severity(severity(update));
+    }
+}
+
+fn severity(update: &PendingUpdate) -> Severity {
+    if update.security {
+        Severity::Security
+    } else if pacman::is_kernel_or_driver(&update.name) {
+        Severity::KernelDriver
+    } else {
+        Severity::Normal
+    }
+}
+
+// Order matches the entries of the sort drop down in window.ui.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SortMode {
+    Name,
+    #[default]
+    Repository,
+    DownloadSize,
+    InstalledDelta,
+    Severity,
+}
+
+impl SortMode {
+    pub fn from_index(index: u32) -> Self {
+        match index {
+            0 => SortMode::Name,
+            2 => SortMode::DownloadSize,
+            3 => SortMode::InstalledDelta,
+            4 => SortMode::Severity,
+            _ => SortMode::Repository,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FilterChip {
+    #[default]
+    All,
+    Security,
+    KernelDriver,
+    Aur,
+}
+
+impl UpdateItem {
+    // Biggest first for sizes and severity, the name breaks every tie.
+    pub fn compare(&self, other: &Self, mode: SortMode) -> std::cmp::Ordering {
+        let ordering = match mode {
+            SortMode::Name => std::cmp::Ordering::Equal,
+            SortMode::Repository => pacman::repo_order(&self.repo()).cmp(&pacman::repo_order(&other.repo())),
+            SortMode::DownloadSize => other.download_size().cmp(&self.download_size()),
+            SortMode::InstalledDelta => other.installed_delta().cmp(&self.installed_delta()),
+            SortMode::Severity => other.severity().cmp(&self.severity()),
+        };
+        ordering.then_with(|| self.name().cmp(&other.name()))
+    }
+
+    pub fn matches(&self, query: &str, chip: FilterChip) -> bool {
+        let chip_matches = match chip {
+            FilterChip::All => true,
+            FilterChip::Security => self.severity() == Severity::Security,
+            FilterChip::KernelDriver => pacman::is_kernel_or_driver(&self.name()),
+            FilterChip::Aur => self.repo() == pacman::AUR_REPO,
+        };
+        chip_matches && self.name().to_lowercase().contains(&query.to_lowercase())
+    }
+}
@@ -1,46 +1,390 @@
 use std::process::Command;
 use std::sync::mpsc;
 use std::thread;
+use std::time::SystemTime;
+
+use gtk::glib;
+
+#[cfg(feature = "alpm")]
+use crate::alpm_backend;
+use crate::auto_update;
+use crate::boot_check::{self, BootCheck};
+use crate::disk_space::{self, Shortage};
+use crate::errors;
+use crate::fleet::{self, FleetConfig, FleetEvent};
+use crate::keyring::{self, KeyringFix, KeyringState};
+use crate::offline_update;
+use crate::pacman::{self, PackageInfo, PendingUpdate};
+use crate::pacman_conf::{self, PacmanConf};
+use crate::prefetch::{self, PrefetchStatus};
+use crate::reboot::{self, RebootStatus};
+use crate::report::Report;
+use crate::runner;
+use crate::transaction::{self, TransactionEvent};
 
 pub struct UpdateManager;
 
 impl UpdateManager {
-    pub fn check_updates(sender: mpsc::Sender<String>) {
+    pub fn check_updates(sender: mpsc::Sender<Result<Vec<PendingUpdate>, String>>) {
+        thread::spawn(move || {
+            #[cfg(feature = "alpm")]
+            let result = alpm_backend::pending_updates();
+            #[cfg(not(feature = "alpm"))]
+            let result = Self::checkupdates();
+            let mut updates = match result {
+                Ok(updates) => updates,
+                Err(e) => {
+                    let _ = sender.send(Err(e));
+                    return;
+                }
+            };
+
+            updates.extend(Self::check_aur_updates());
+            updates.extend(Self::check_flatpak_updates());
+            let _ = sender.send(Ok(updates));
+        });
+    }
+
+    #[cfg(not(feature = "alpm"))]
+    fn checkupdates() -> Result<Vec<PendingUpdate>, String> {
+        let mut updates = pacman::checkupdates()?;
+
+        let names: Vec<&str> = updates.iter().map(|update| update.name.as_str()).collect();
+        let sync = pacman::sync_info(&names);
+        let local = pacman::local_info(&names);
+        let security = pacman::security_updates();
+        for update in updates.iter_mut() {
+            if let Some(info) = sync.get(&update.name) {
+                let installed = local.get(&update.name).map(|info| info.installed_size).unwrap_or(0);
+                update.repo = info.repo.clone();
+                update.download_size = info.download_size;
+                update.installed_delta = info.installed_size as i64 - installed as i64;
+            }
+            update.security = security.contains(&update.name);
+        }
+        Ok(updates)
+    }
+
+    fn check_aur_updates() -> Vec<PendingUpdate> {
+        let Some(helper) = pacman::aur_helper() else {
+            return Vec::new();
+        };
+        match runner::command(helper).arg("-Qua").output() {
+            Ok(res) => pacman::parse_update_lines(&String::from_utf8_lossy(&res.stdout), pacman::AUR_REPO),
+            Err(e) => {
+                eprintln!("Failed to check AUR updates with {}: {}", helper, e);
+                Vec::new()
+            }
+        }
+    }
+
+    fn check_flatpak_updates() -> Vec<PendingUpdate> {
+        if glib::find_program_in_path("flatpak").is_none() {
+            return Vec::new();
+        }
+        match runner::command("flatpak")
+            .args(["remote-ls", "--updates", "--columns=application,version"])
+            .output()
+        {
+            Ok(res) => String::from_utf8_lossy(&res.stdout)
+                .lines()
+                .filter_map(|line| {
+                    let mut parts = line.split('\t');
+                    let name = parts.next()?.trim();
+                    if name.is_empty() {
+                        return None;
+                    }
+                    Some(PendingUpdate {
+                        name: name.to_string(),
+                        old_version: String::new(),
+                        new_version: parts.next().unwrap_or("").trim().to_string(),
+                        repo: pacman::FLATPAK_REPO.to_string(),
+                        ..Default::default()
+                    })
+                })
+                .collect(),
+            Err(e) => {
+                eprintln!("Failed to check Flatpak updates: {}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    // Builds the command that updates `pkgs` from wherever they come from.
+    fn install_command(pkgs: &[String], repo: &str) -> Command {
+        let mut command;
+        match repo {
+            pacman::AUR_REPO => {
+                // AUR helpers refuse to run as root, they escalate on their own.
+                command = runner::command(pacman::aur_helper().unwrap_or("paru"));
+                command.args(["-S", "--noconfirm", "--sudo", "pkexec"]);
+            }
+            pacman::FLATPAK_REPO => {
+                command = runner::command("flatpak");
+                command.args(["update", "-y", "--noninteractive"]);
+            }
+            #[cfg(feature = "alpm")]
+            _ => return alpm_backend::command(pkgs, false, pkgs, &[]),
+            #[cfg(not(feature = "alpm"))]
+            _ => {
+                // No --noconfirm, pacman's questions are relayed by transaction::run.
+                command = runner::command("pkexec");
+                command.args(["pacman", "-y", "-S"]);
+                command.args(prefetch::cachedir_args());
+            }
+        }
+        command.args(pkgs);
+        command
+    }
+
+    // The full system upgrade, leaving out held packages. Run it with
+    // transaction::run.
+    pub fn upgrade_command(pending: &[String], ignored: &[String]) -> Command {
+        #[cfg(feature = "alpm")]
+        return alpm_backend::command(&[], true, pending, ignored);
+
+        #[cfg(not(feature = "alpm"))]
+        {
+            let mut command = crate::keyring::system_upgrade(pending);
+            command.args(prefetch::cachedir_args());
+            command.args(crate::holds::ignore_args(ignored));
+            command
+        }
+    }
+
+    // Ends with TransactionEvent::Finished, carrying pacman's output (see
+    // errors::PacmanError::classify for the failure case).
+    pub fn install_packages(pkgs: Vec<String>, repo: String, sender: mpsc::Sender<TransactionEvent>) {
+        println!("Thread started for: {}", pkgs.join(" "));
         thread::spawn(move || {
-            let output = Command::new("checkupdates").output();
-            let result = match output {
-                Ok(res) => String::from_utf8_lossy(&res.stdout).to_string(),
-                Err(_) => "Error".to_string(),
+            let command = Self::install_command(&pkgs, &repo);
+            let result = match repo.as_str() {
+                pacman::AUR_REPO | pacman::FLATPAK_REPO => Self::run_noninteractive(command),
+                _ => transaction::run(command, &sender),
+            };
+            if result.is_ok() {
+                println!("Success: {} installed.", pkgs.join(" "));
+            }
+            let _ = sender.send(TransactionEvent::Finished(result));
+        });
+    }
+
+    fn run_noninteractive(mut command: Command) -> Result<String, String> {
+        match command.output()
+        {
+            Ok(output) => {
+              
+                if output.status.success() {
+                    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+                } else {
+                   
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    eprintln!("Command ran but failed with exit code: {}", output.status);
+                    eprintln!("Stderr: {}", stderr);
+                    Err(stderr.to_string())
+                }
+            }
+            Err(e) => {
+              
+                eprintln!("Failed to launch the update: {}", e);
+                Err(e.to_string())
+            }
+        }
+    }
+
+    pub fn find_orphans(sender: mpsc::Sender<Vec<PackageInfo>>) {
+        thread::spawn(move || {
+            // -Qdtq exits with 1 when there are no orphans, so only the
+            // output matters here.
+            let names: Vec<String> = match pacman::query(&["-Qdtq"]) {
+                Ok(res) => String::from_utf8_lossy(&res.stdout)
+                    .lines()
+                    .map(|line| line.trim().to_string())
+                    .filter(|line| !line.is_empty())
+                    .collect(),
+                Err(e) => {
+                    eprintln!("Failed to list orphans: {}", e);
+                    Vec::new()
+                }
+            };
+
+            if names.is_empty() {
+                let _ = sender.send(Vec::new());
+                return;
+            }
+
+            let mut args = vec!["-Qi"];
+            args.extend(names.iter().map(String::as_str));
+            let orphans = match pacman::query(&args) {
+                Ok(res) => pacman::parse_info(&String::from_utf8_lossy(&res.stdout)),
+                Err(e) => {
+                    eprintln!("Failed to query orphans: {}", e);
+                    Vec::new()
+                }
+            };
+            let _ = sender.send(orphans);
+        });
+    }
+
+    // Lists everything `pacman -Rns` would take with it, without touching the system.
+    pub fn preview_removal(pkgs: Vec<String>, sender: mpsc::Sender<Result<Vec<String>, String>>) {
+        thread::spawn(move || {
+            let mut args = vec!["-Rns", "--print", "--print-format", "%n"];
+            args.extend(pkgs.iter().map(String::as_str));
+            let result = match pacman::query(&args) {
+                Ok(res) if res.status.success() => Ok(String::from_utf8_lossy(&res.stdout)
+                    .lines()
+                    .map(|line| line.trim().to_string())
+                    .filter(|line| !line.is_empty())
+                    .collect()),
+                Ok(res) => Err(String::from_utf8_lossy(&res.stderr).trim().to_string()),
+                Err(e) => Err(e.to_string()),
             };
             let _ = sender.send(result);
         });
     }
 
-    pub fn install_package(pkg: String, sender: mpsc::Sender<String>) {
-        println!("Thread started for: {}", pkg);
+    pub fn remove_packages(pkgs: Vec<String>, sender: mpsc::Sender<String>) {
+        println!("Thread started for removing: {}", pkgs.join(" "));
         thread::spawn(move || {
-            match Command::new("pkexec")
-                .args(["pacman", "-y", "-S", &pkg, "--noconfirm"])
+            match runner::command("pkexec")
+                .arg("pacman")
+                .args(["-Rns", "--noconfirm"])
+                .args(&pkgs)
                 .output()
             {
                 Ok(output) => {
-                  
                     if output.status.success() {
-                        println!("Success: {} installed.", pkg);
+                        println!("Success: {} removed.", pkgs.join(" "));
                         sender.send("Ok".to_string()).unwrap();
                     } else {
-                       
                         eprintln!("Command ran but failed with exit code: {}", output.status);
                         eprintln!("Stderr: {}", String::from_utf8_lossy(&output.stderr));
                         sender.send("Err".to_string()).unwrap();
                     }
                 }
                 Err(e) => {
-                  
                     eprintln!("Failed to even launch pkexec: {}", e);
                     sender.send("Err".to_string()).unwrap();
                 }
             }
         });
     }
+
+    // Fire and forget, failed reports stay queued for the next one.
+    pub fn report_check(updates: Vec<PendingUpdate>) {
+        let Some(config) = FleetConfig::load() else {
+            return;
+        };
+        thread::spawn(move || {
+            fleet::send(&config, FleetEvent::Check, &Report::pending(&updates));
+        });
+    }
+
+    pub fn report_upgrade() {
+        let Some(config) = FleetConfig::load() else {
+            return;
+        };
+        thread::spawn(move || {
+            if let Some(report) = Report::last_transaction() {
+                fleet::send(&config, FleetEvent::Upgrade, &report);
+            }
+        });
+    }
+
+    pub fn last_transaction(sender: mpsc::Sender<Option<Report>>) {
+        thread::spawn(move || {
+            let _ = sender.send(Report::last_transaction());
+        });
+    }
+
+    pub fn check_reboot(sender: mpsc::Sender<RebootStatus>) {
+        thread::spawn(move || {
+            let _ = sender.send(reboot::analyze());
+        });
+    }
+
+    pub fn check_boot(upgraded: Vec<String>, output: String, started: SystemTime, sender: mpsc::Sender<BootCheck>) {
+        thread::spawn(move || {
+            let _ = sender.send(boot_check::check_after_upgrade(&upgraded, &output, started));
+        });
+    }
+
+    pub fn restart_services(user: Vec<String>, sender: mpsc::Sender<String>) {
+        thread::spawn(move || {
+            let status = if reboot::restart_services(&user) { "Ok" } else { "Err" };
+            let _ = sender.send(status.to_string());
+        });
+    }
+
+    pub fn schedule_offline_update(ignored: Vec<String>, sender: mpsc::Sender<String>) {
+        println!("thread started for preparing the offline update");
+        thread::spawn(move || {
+            let status = if offline_update::schedule(&ignored) { "Ok" } else { "Err" };
+            let _ = sender.send(status.to_string());
+        });
+    }
+
+    pub fn cancel_offline_update(sender: mpsc::Sender<String>) {
+        thread::spawn(move || {
+            let status = if offline_update::cancel() { "Ok" } else { "Err" };
+            let _ = sender.send(status.to_string());
+        });
+    }
+
+    // With `download` unset this only reports what is already cached.
+    pub fn prefetch(download: bool, sender: mpsc::Sender<PrefetchStatus>) {
+        thread::spawn(move || {
+            let status = if download { prefetch::download() } else { prefetch::status() };
+            let _ = sender.send(status);
+        });
+    }
+
+    pub fn latest_news(sender: mpsc::Sender<Option<String>>) {
+        thread::spawn(move || {
+            let _ = sender.send(auto_update::latest_news());
+        });
+    }
+
+    pub fn check_keyring(sender: mpsc::Sender<KeyringState>) {
+        thread::spawn(move || {
+            let _ = sender.send(keyring::freshness());
+        });
+    }
+
+    pub fn repair_keyring(fix: KeyringFix, sender: mpsc::Sender<String>) {
+        println!("thread started for repairing the keyring: {:?}", fix);
+        thread::spawn(move || {
+            let status = if fix.run() { "Ok" } else { "Err" };
+            let _ = sender.send(status.to_string());
+        });
+    }
+
+    pub fn check_disk_space(updates: Vec<PendingUpdate>, sender: mpsc::Sender<Vec<Shortage>>) {
+        thread::spawn(move || {
+            let _ = sender.send(disk_space::check(&updates));
+        });
+    }
+
+    pub fn clean_cache(sender: mpsc::Sender<String>) {
+        println!("thread started for cleaning the package cache");
+        thread::spawn(move || {
+            let status = if disk_space::clean_cache() { "Ok" } else { "Err" };
+            let _ = sender.send(status.to_string());
+        });
+    }
+
+    pub fn remove_lock(sender: mpsc::Sender<String>) {
+        thread::spawn(move || {
+            let status = if errors::remove_lock() { "Ok" } else { "Err" };
+            let _ = sender.send(status.to_string());
+        });
+    }
+
+    pub fn save_pacman_conf(conf: PacmanConf, sender: mpsc::Sender<Result<(), String>>) {
+        println!("thread started for saving {}", conf.path.display());
+        thread::spawn(move || {
+            let _ = sender.send(pacman_conf::save(&conf));
+        });
+    }
 }
@@ -1,7 +1,14 @@
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
-use gtk::glib;
+use gtk::{gio, glib};
+use crate::errors::PacmanError;
+use crate::holds;
+use crate::pacman;
+use crate::question_dialog;
+use crate::transaction::TransactionEvent;
+use crate::update_item::{Severity, UpdateItem};
 use crate::update_manager::UpdateManager;
+use crate::window::UpdaterWindow;
 use std::cell::RefCell;
 
 mod imp {
@@ -11,7 +18,10 @@ mod imp {
     pub struct UpdateRow {
         pub package: RefCell<String>,
         pub version: RefCell<String>,
+        pub item: RefCell<Option<UpdateItem>>,
         pub on_refresh: RefCell<Option<std::sync::mpsc::Sender<()>>>,
+        pub install_button: gtk::Button,
+        pub install_sender: RefCell<Option<std::sync::mpsc::Sender<TransactionEvent>>>,
     }
 
     #[glib::object_subclass]
@@ -33,20 +43,104 @@ glib::wrapper! {
 }
 
 impl UpdateRow {
-    pub fn new(package: &str, version: &str, on_refresh: std::sync::mpsc::Sender<()>) -> Self {
+    pub fn new(update: &UpdateItem, on_refresh: std::sync::mpsc::Sender<()>) -> Self {
         let obj: Self = glib::Object::builder().build();
-        obj.set_data(package, version, on_refresh);
+        obj.set_data(update, on_refresh);
         obj.setup_ui();
         obj
     }
 
-    fn set_data(&self, package: &str, version: &str, on_refresh: std::sync::mpsc::Sender<()>) {
+    fn set_data(&self, update: &UpdateItem, on_refresh: std::sync::mpsc::Sender<()>) {
         let imp = self.imp();
-        imp.package.replace(package.to_string());
-        imp.version.replace(version.to_string());
+        imp.package.replace(update.name());
+        imp.version.replace(update.new_version());
+        imp.item.replace(Some(update.clone()));
         imp.on_refresh.replace(Some(on_refresh));
     }
 
+    pub fn package(&self) -> String {
+        self.imp().package.borrow().clone()
+    }
+
+    pub fn install(&self) {
+        let imp = self.imp();
+        let Some(sender) = imp.install_sender.borrow().clone() else {
+            return;
+        };
+        if !imp.install_button.is_sensitive() {
+            return;
+        }
+        let Some(window) = self.window() else {
+            return;
+        };
+        if !window.start_partial_update(&[self.package()]) {
+            return;
+        }
+        imp.install_button.set_label("Updating...");
+        imp.install_button.set_sensitive(false);
+        let repo = imp.item.borrow().as_ref().map(|item| item.repo()).unwrap_or_default();
+        UpdateManager::install_packages(vec![self.package()], repo, sender);
+    }
+
+    fn window(&self) -> Option<UpdaterWindow> {
+        self.ancestor(UpdaterWindow::static_type()).and_downcast::<UpdaterWindow>()
+    }
+
+    pub fn set_button_sensitive(&self, sensitive: bool) {
+        let mut child = self.first_child();
+        while let Some(widget) = child {
+            if let Ok(button) = widget.clone().downcast::<gtk::Button>() {
+                button.set_sensitive(sensitive);
+            }
+            child = widget.next_sibling();
+        }
+    }
+
+    // Hold and skip only write the settings, the window regroups the list
+    // when they change.
+    fn build_hold_menu(&self) -> gtk::MenuButton {
+        let imp = self.imp();
+        let package = imp.package.borrow().clone();
+        let version = imp.version.borrow().clone();
+        let held = imp.item.borrow().as_ref().map(|item| item.held()).unwrap_or(false);
+
+        let menu = gio::Menu::new();
+        if held {
+            menu.append(Some("Stop Holding"), Some("row.release"));
+        } else {
+            menu.append(Some("Hold This Package"), Some("row.hold"));
+            menu.append(Some("Skip This Version"), Some("row.skip"));
+        }
+
+        let hold_action = gio::ActionEntry::builder("hold")
+            .activate(glib::clone!(#[strong] package, move |_: &gio::SimpleActionGroup, _, _| {
+                holds::hold(&package);
+            }))
+            .build();
+        let skip_action = gio::ActionEntry::builder("skip")
+            .activate(glib::clone!(#[strong] package, #[strong] version, move |_: &gio::SimpleActionGroup, _, _| {
+                holds::skip_version(&package, &version);
+            }))
+            .build();
+        let release_action = gio::ActionEntry::builder("release")
+            .activate(glib::clone!(#[strong] package, move |_: &gio::SimpleActionGroup, _, _| {
+                holds::release(&package);
+            }))
+            .build();
+
+        let actions = gio::SimpleActionGroup::new();
+        actions.add_action_entries([hold_action, skip_action, release_action]);
+        self.insert_action_group("row", Some(&actions));
+
+        gtk::MenuButton::builder()
+            .icon_name("view-more-symbolic")
+            .menu_model(&menu)
+            .valign(gtk::Align::Center)
+            .css_classes(["flat"])
+            .tooltip_text("More Options")
+            .build()
+    }
+
     fn setup_ui(&self) {
         let imp = self.imp();
         let package = imp.package.borrow().clone();
@@ -60,53 +154,114 @@ impl UpdateRow {
         self.set_margin_bottom(6);
 
         let pkg_label = gtk::Label::builder()
-            .label(&format!("{} - {}", package, version))
+            .label(format!("{} - {}", package, version))
+            .halign(gtk::Align::Start)
+            .build();
+
+        let details_label = gtk::Label::builder()
             .halign(gtk::Align::Start)
             .hexpand(true)
+            .css_classes(["dim-label", "caption"])
             .build();
 
-        let install_button = gtk::Button::builder()
-            .label("Update")
+        let downloaded_label = gtk::Label::builder()
+            .label("Downloaded")
             .valign(gtk::Align::Center)
+            .css_classes(["badge", "caption"])
+            .tooltip_text("Already in the package cache, installs without downloading")
+            .build();
+        let boot_label = gtk::Label::builder()
+            .label("Boot Critical")
+            .valign(gtk::Align::Center)
+            .visible(pacman::is_boot_critical(&package))
+            .css_classes(["badge", "warning", "caption"])
+            .tooltip_text("Affects booting, the kernel images and modules are checked after updating")
             .build();
 
-        let (tx, rx) = std::sync::mpsc::channel::<String>();
-
-        if let Some(on_refresh) = imp.on_refresh.borrow().as_ref() {
-            let on_refresh = on_refresh.clone();
-            install_button.connect_clicked(glib::clone!(@weak install_button,
-                @strong package,
-                @strong version,
-                 => move |_| {
-                install_button.set_label("Updating...");
-                install_button.set_sensitive(false);
-                UpdateManager::install_package(package.clone(), tx.clone());
-            }));
+        if let Some(item) = imp.item.borrow().as_ref() {
+            item.bind_property("downloaded", &downloaded_label, "visible")
+                .sync_create()
+                .build();
+            // The item is reused by later checks, which may find a new size.
+            details_label.set_label(&details(item));
+            for property in ["repo", "download-size", "severity"] {
+                item.connect_notify_local(Some(property), glib::clone!(#[weak] details_label, move |item, _| {
+                    details_label.set_label(&details(item));
+                }));
+            }
+        }
+
+        // Goes through the window, see UpdaterWindow::update_package.
+        let install_button = imp.install_button.clone();
+        install_button.set_label("Update");
+        install_button.set_valign(gtk::Align::Center);
+        install_button.set_action_name(Some("win.update-package"));
+        install_button.set_action_target_value(Some(&package.to_variant()));
+
+        let hold_button = self.build_hold_menu();
 
+        let (tx, rx) = std::sync::mpsc::channel::<TransactionEvent>();
+        imp.install_sender.replace(Some(tx));
+
+        if imp.on_refresh.borrow().is_some() {
             glib::timeout_add_local(std::time::Duration::from_millis(100), glib::clone!(
-                @weak install_button,
-                @strong package,
-                @strong version,
-                @weak pkg_label => @default-return glib::ControlFlow::Break, move || {
+                #[weak(rename_to = obj)] self,
+                #[weak] install_button,
+                #[strong] package,
+                #[weak] details_label,
+                #[weak] downloaded_label,
+                #[weak] boot_label,
+                #[weak] hold_button,
+                #[weak] pkg_label,
+                #[upgrade_or] glib::ControlFlow::Break,
+                move || {
+
+                if let Ok(event) = rx.try_recv() {
 
-                if let Ok(status) = rx.try_recv() {
+                    let result = match event {
+                        TransactionEvent::Question(question, reply) => {
+                            question_dialog::present(&install_button, question, reply);
+                            return glib::ControlFlow::Continue;
+                        }
+                        TransactionEvent::Progress(percent, _) => {
+                            install_button.set_label(&format!("{}%", percent));
+                            return glib::ControlFlow::Continue;
+                        }
+                        TransactionEvent::Downloaded(time) => {
+                            if let Some(window) = obj.window() {
+                                window.record_download(std::slice::from_ref(&package), time);
+                            }
+                            return glib::ControlFlow::Continue;
+                        }
+                        TransactionEvent::Finished(result) => result,
+                    };
+                    if let Some(window) = obj.window() {
+                        window.finish_partial_update(&result);
+                    }
 
-                    if status == "Ok" {
+                    if result.is_ok() {
                         install_button.set_label("Update");
                         println!("Successfull");
                         // imp.label.set_text("Success"); 
                         install_button.set_sensitive(true);
                         install_button.unparent();
+                        details_label.unparent();
+                        downloaded_label.unparent();
+                        boot_label.unparent();
+                        hold_button.unparent();
                         pkg_label.unparent();
+                        if let Some(on_refresh) = obj.imp().on_refresh.borrow().as_ref() {
+                            let _ = on_refresh.send(());
+                        }
                         return glib::ControlFlow::Break;
                     }
 
-                    else {
-                        pkg_label.set_text(&format!("{} - Error, read the log", package));
+                    else if let Err(log) = result {
+                        let error = PacmanError::classify(&log);
+                        pkg_label.set_text(&format!("{} - {}", package, error.title()));
                         install_button.set_label("Update");
                         println!("Failed");
                         install_button.set_sensitive(true);
-                        // let _ = on_refresh.send(()); 
                     }
                 }
                 glib::ControlFlow::Continue
@@ -114,9 +269,27 @@ impl UpdateRow {
         }
 
         self.append(&pkg_label);
+        self.append(&details_label);
+        self.append(&boot_label);
+        self.append(&downloaded_label);
         self.append(&install_button);
+        self.append(&hold_button);
 
     
     }
 
 }
+
+// "Extra · 2.1 MB · Security"
+fn details(item: &UpdateItem) -> String {
+    let mut details = vec![pacman::repo_title(&item.repo())];
+    if item.download_size() > 0 {
+        details.push(pacman::format_size(item.download_size()));
+    }
+    match item.severity() {
+        Severity::Security => details.push("Security".to_string()),
+        Severity::KernelDriver => details.push("Kernel/Driver".to_string()),
+        Severity::Normal => {}
+    }
+    details.join(" · ")
+}
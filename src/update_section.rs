@@ -0,0 +1,303 @@
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+use gtk::{gio, glib};
+use crate::errors::PacmanError;
+use crate::pacman;
+use crate::question_dialog;
+use crate::transaction::TransactionEvent;
+use crate::update_item::UpdateItem;
+use crate::update_manager::UpdateManager;
+use crate::update_row::UpdateRow;
+use crate::window::UpdaterWindow;
+use std::cell::{OnceCell, RefCell};
+
+// Pseudo repository of the section collecting held and skipped updates.
+pub const HELD: &str = "held";
+
+mod imp {
+    use super::*;
+
+    #[derive(Default)]
+    pub struct UpdateSection {
+        // None when the section mixes updates from every source.
+        pub repo: RefCell<Option<String>>,
+        pub store: OnceCell<gio::ListStore>,
+        pub filter_model: OnceCell<gtk::FilterListModel>,
+        pub on_refresh: RefCell<Option<std::sync::mpsc::Sender<()>>>,
+        pub expand_button: gtk::ToggleButton,
+        pub title_label: gtk::Label,
+        pub count_label: gtk::Label,
+        pub update_button: gtk::Button,
+        pub revealer: gtk::Revealer,
+        pub list: gtk::ListBox,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for UpdateSection {
+        const NAME: &'static str = "UpdateSection";
+        type Type = super::UpdateSection;
+        type ParentType = gtk::Box;
+    }
+
+    impl ObjectImpl for UpdateSection {}
+    impl WidgetImpl for UpdateSection {}
+    impl BoxImpl for UpdateSection {}
+}
+
+glib::wrapper! {
+    pub struct UpdateSection(ObjectSubclass<imp::UpdateSection>)
+        @extends gtk::Widget, gtk::Box,
+        @implements gtk::Orientable;
+}
+
+impl UpdateSection {
+    pub fn new(
+        repo: Option<&str>,
+        filter: &gtk::CustomFilter,
+        sorter: &gtk::CustomSorter,
+        on_refresh: std::sync::mpsc::Sender<()>,
+    ) -> Self {
+        let obj: Self = glib::Object::builder().build();
+        let imp = obj.imp();
+        imp.repo.replace(repo.map(str::to_string));
+        imp.on_refresh.replace(Some(on_refresh));
+
+        let store = gio::ListStore::new::<UpdateItem>();
+        let filter_model = gtk::FilterListModel::new(Some(store.clone()), Some(filter.clone()));
+        let sort_model = gtk::SortListModel::new(Some(filter_model.clone()), Some(sorter.clone()));
+        imp.store.set(store).unwrap();
+        imp.filter_model.set(filter_model).unwrap();
+
+        obj.setup_ui(&sort_model);
+        obj
+    }
+
+    pub fn repo(&self) -> Option<String> {
+        self.imp().repo.borrow().clone()
+    }
+
+    fn store(&self) -> &gio::ListStore {
+        self.imp().store.get().unwrap()
+    }
+
+    fn setup_ui(&self, model: &gtk::SortListModel) {
+        let imp = self.imp();
+
+        self.set_orientation(gtk::Orientation::Vertical);
+        self.set_spacing(6);
+        self.add_css_class("update-section");
+
+        // Held updates start collapsed, they are out of the way on purpose.
+        let held = self.repo().as_deref() == Some(HELD);
+        imp.expand_button.set_icon_name(if held { "pan-end-symbolic" } else { "pan-down-symbolic" });
+        imp.expand_button.set_active(!held);
+        imp.expand_button.add_css_class("flat");
+        imp.expand_button.set_tooltip_text(Some("Show or hide this section"));
+
+        let title = match self.repo() {
+            Some(_) if held => "Held".to_string(),
+            Some(repo) => pacman::repo_title(&repo),
+            None => "All Updates".to_string(),
+        };
+        imp.title_label.set_text(&title);
+        imp.title_label.add_css_class("heading");
+
+        imp.count_label.add_css_class("dim-label");
+        imp.count_label.set_halign(gtk::Align::Start);
+        imp.count_label.set_hexpand(true);
+
+        imp.update_button.set_label("Update Section");
+        imp.update_button.set_valign(gtk::Align::Center);
+        // A mixed section can't be installed in one go, "Update All" covers it.
+        imp.update_button.set_visible(self.repo().is_some() && !held);
+
+        let header = gtk::Box::builder()
+            .orientation(gtk::Orientation::Horizontal)
+            .spacing(6)
+            .build();
+        header.append(&imp.expand_button);
+        header.append(&imp.title_label);
+        header.append(&imp.count_label);
+        header.append(&imp.update_button);
+
+        imp.list.set_selection_mode(gtk::SelectionMode::None);
+        imp.list.add_css_class("boxed-list");
+
+        let on_refresh = imp.on_refresh.borrow().clone().unwrap();
+        imp.list.bind_model(Some(model), move |item| {
+            let item = item.downcast_ref::<UpdateItem>().unwrap();
+            let row = UpdateRow::new(item, on_refresh.clone());
+            gtk::ListBoxRow::builder()
+                .child(&row)
+                .activatable(false)
+                .selectable(false)
+                .css_classes(["non-selectable-item"])
+                .build()
+                .upcast()
+        });
+
+        imp.revealer.set_child(Some(&imp.list));
+
+        imp.expand_button.bind_property("active", &imp.revealer, "reveal-child")
+            .sync_create()
+            .build();
+        imp.expand_button.connect_toggled(|button| {
+            button.set_icon_name(if button.is_active() { "pan-down-symbolic" } else { "pan-end-symbolic" });
+        });
+
+        imp.update_button.connect_clicked(glib::clone!(#[weak(rename_to = obj)] self, move |_| {
+            obj.update_section();
+        }));
+
+        imp.filter_model.get().unwrap().connect_items_changed(glib::clone!(#[weak(rename_to = obj)] self, move |_, _, _, _| {
+            obj.update_count();
+        }));
+
+        self.append(&header);
+        self.append(&imp.revealer);
+    }
+
+    // Rows of items that stay are kept, only those that come or go are
+    // created or destroyed.
+    pub fn sync_items(&self, items: &[UpdateItem]) {
+        let store = self.store();
+        for position in (0..store.n_items()).rev() {
+            let keep = store
+                .item(position)
+                .and_downcast::<UpdateItem>()
+                .is_some_and(|item| items.contains(&item));
+            if !keep {
+                store.remove(position);
+            }
+        }
+        for item in items {
+            if store.find(item).is_none() {
+                store.append(item);
+            }
+        }
+    }
+
+    fn update_count(&self) {
+        let total = self.store().n_items();
+        let visible = self.imp().filter_model.get().unwrap().n_items();
+
+        let text = if visible != total {
+            format!("{} of {} updates", visible, total)
+        } else if total == 1 {
+            "1 update".to_string()
+        } else {
+            format!("{} updates", total)
+        };
+        self.imp().count_label.set_text(&text);
+        self.set_visible(visible > 0);
+    }
+
+    pub fn set_buttons_sensitive(&self, sensitive: bool) {
+        let imp = self.imp();
+        imp.update_button.set_sensitive(sensitive);
+
+        let mut child = imp.list.first_child();
+        while let Some(widget) = child {
+            if let Some(row) = widget.downcast_ref::<gtk::ListBoxRow>().and_then(|row| row.child()) {
+                if let Ok(row) = row.downcast::<UpdateRow>() {
+                    row.set_button_sensitive(sensitive);
+                }
+            }
+            child = widget.next_sibling();
+        }
+    }
+
+    pub fn row(&self, package: &str) -> Option<UpdateRow> {
+        let mut child = self.imp().list.first_child();
+        while let Some(widget) = child {
+            if let Some(row) = widget.downcast_ref::<gtk::ListBoxRow>().and_then(|row| row.child()) {
+                if let Ok(row) = row.downcast::<UpdateRow>() {
+                    if row.package() == package {
+                        return Some(row);
+                    }
+                }
+            }
+            child = widget.next_sibling();
+        }
+        None
+    }
+
+    fn window(&self) -> Option<UpdaterWindow> {
+        self.ancestor(UpdaterWindow::static_type()).and_downcast::<UpdaterWindow>()
+    }
+
+    fn packages(&self) -> Vec<String> {
+        self.store()
+            .iter::<UpdateItem>()
+            .filter_map(Result::ok)
+            .map(|item| item.name())
+            .collect()
+    }
+
+    fn update_section(&self) {
+        let imp = self.imp();
+        let Some(repo) = self.repo() else {
+            return;
+        };
+        let pkgs = self.packages();
+        if pkgs.is_empty() {
+            return;
+        }
+        let Some(window) = self.window() else {
+            return;
+        };
+        if !window.start_partial_update(&pkgs) {
+            return;
+        }
+
+        imp.update_button.set_label("Updating...");
+
+        let (sender, receiver) = std::sync::mpsc::channel::<TransactionEvent>();
+        UpdateManager::install_packages(pkgs.clone(), repo.clone(), sender);
+
+        glib::timeout_add_local(std::time::Duration::from_millis(100),
+            glib::clone!(#[weak(rename_to = obj)] self, #[upgrade_or] glib::ControlFlow::Break, move || {
+                if let Ok(event) = receiver.try_recv() {
+                    let result = match event {
+                        TransactionEvent::Question(question, reply) => {
+                            question_dialog::present(&obj, question, reply);
+                            return glib::ControlFlow::Continue;
+                        }
+                        TransactionEvent::Progress(percent, _) => {
+                            obj.imp().update_button.set_label(&format!("Updating... {}%", percent));
+                            return glib::ControlFlow::Continue;
+                        }
+                        TransactionEvent::Downloaded(time) => {
+                            if let Some(window) = obj.window() {
+                                window.record_download(&pkgs, time);
+                            }
+                            return glib::ControlFlow::Continue;
+                        }
+                        TransactionEvent::Finished(result) => result,
+                    };
+                    let imp = obj.imp();
+                    imp.update_button.set_label("Update Section");
+                    if let Some(window) = obj.window() {
+                        window.finish_partial_update(&result);
+                    }
+
+                    match result {
+                        Ok(_) => {
+                            println!("Section {} updated", repo);
+                            if let Some(on_refresh) = imp.on_refresh.borrow().as_ref() {
+                                let _ = on_refresh.send(());
+                            }
+                        }
+                        Err(log) => {
+                            println!("Section {} failed", repo);
+                            imp.count_label.set_text(&PacmanError::classify(&log).title());
+                        }
+                    }
+                    glib::ControlFlow::Break
+                } else {
+                    glib::ControlFlow::Continue
+                }
+            })
+        );
+    }
+}
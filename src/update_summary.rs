@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+
+use crate::pacman;
+use crate::update_item::{Severity, UpdateItem};
+
+// Totals of the pending updates for the bar above the list. Sizes are those
+// of the sync databases, AUR and Flatpak updates don't have any. Held updates
+// are listed but left out of the sizes, they are not going to be installed.
+#[derive(Debug, Clone, Default)]
+pub struct UpdateSummary {
+    pub count: usize,
+    // What still has to be fetched, downloaded packages don't count.
+    pub download: u64,
+    pub installed_delta: i64,
+    // Recent mirror throughput in bytes per second, None until measured.
+    pub throughput: Option<u64>,
+    // Keyed by repo_order, so official repositories come first.
+    repos: BTreeMap<(u8, String), usize>,
+    security: usize,
+    kernel_driver: usize,
+    held: usize,
+}
+
+impl UpdateSummary {
+    pub fn new(items: &[UpdateItem], throughput: Option<u64>) -> Self {
+        let mut summary = Self {
+            count: items.len(),
+            throughput,
+            ..Default::default()
+        };
+        for item in items {
+            if item.held() {
+                summary.held += 1;
+            } else {
+                if !item.downloaded() {
+                    summary.download += item.download_size();
+                }
+                summary.installed_delta += item.installed_delta();
+            }
+            *summary.repos.entry(pacman::repo_order(&item.repo())).or_insert(0) += 1;
+            match item.severity() {
+                Severity::Security => summary.security += 1,
+                Severity::KernelDriver => summary.kernel_driver += 1,
+                Severity::Normal => {}
+            }
+        }
+        summary
+    }
+
+    // "12 updates · 340 MB to download · +12 MB installed · about 3 min"
+    pub fn headline(&self) -> String {
+        let mut parts = vec![if self.count == 1 { "1 update".to_string() } else { format!("{} updates", self.count) }];
+        if self.download > 0 {
+            parts.push(format!("{} to download", pacman::format_size(self.download)));
+        } else {
+            parts.push("Nothing to download".to_string());
+        }
+        parts.push(format!("{} installed", format_delta(self.installed_delta)));
+        if let Some(estimate) = self.estimate() {
+            parts.push(estimate);
+        }
+        parts.join(" · ")
+    }
+
+    // "core 3 · extra 8 · AUR 1 · 2 security · 1 kernel/driver · 1 held"
+    pub fn breakdown(&self) -> String {
+        let mut parts: Vec<String> = self.repos
+            .iter()
+            .map(|((_, repo), count)| format!("{} {}", pacman::repo_title(repo), count))
+            .collect();
+        if self.security > 0 {
+            parts.push(format!("{} security", self.security));
+        }
+        if self.kernel_driver > 0 {
+            parts.push(format!("{} kernel/driver", self.kernel_driver));
+        }
+        if self.held > 0 {
+            parts.push(format!("{} held", self.held));
+        }
+        parts.join(" · ")
+    }
+
+    fn estimate(&self) -> Option<String> {
+        if self.download == 0 {
+            return None;
+        }
+        let Some(throughput) = self.throughput.filter(|throughput| *throughput > 0) else {
+            return Some("download time unknown".to_string());
+        };
+        let minutes = self.download / throughput / 60;
+        Some(match minutes {
+            0 => "under a minute".to_string(),
+            1 => "about 1 min".to_string(),
+            2..=59 => format!("about {} min", minutes),
+            _ => format!("about {} h {} min", minutes / 60, minutes % 60),
+        })
+    }
+}
+
+fn format_delta(bytes: i64) -> String {
+    let sign = if bytes < 0 { "-" } else { "+" };
+    format!("{}{}", sign, pacman::format_size(bytes.unsigned_abs()))
+}
@@ -3,32 +3,121 @@ use gtk::subclass::prelude::*;
 use adw::prelude::*;
 use adw::subclass::prelude::*;
 use gtk::{gio, glib};
+use crate::app_state::{AppState, StateEvent, StateExport};
+use crate::auto_update::{self, MaintenanceWindow};
+use crate::boot_check::{self, BootCheck};
+use crate::conditions::{Activity, Conditions, Deferral};
+use crate::disk_space::Shortage;
+use crate::errors::{ErrorAction, PacmanError};
+use crate::holds::Holds;
+use crate::keyring::{self, KeyringFix, KeyringState};
+use crate::offline_update;
+use crate::orphans_dialog::OrphansDialog;
+use crate::repos_dialog::ReposDialog;
+use crate::report::{self, Report, ReportFormat, ReportKind};
+use crate::state_store::StoredState;
+use crate::pacman::{self, PendingUpdate};
+use crate::prefetch::{self, PrefetchStatus};
+use crate::question_dialog;
+use crate::reboot::{self, RebootStatus};
+use crate::transaction::{self, TransactionEvent};
+use crate::update_item::{FilterChip, SortMode, UpdateItem};
 use crate::update_manager::UpdateManager;
-use crate::update_row::UpdateRow;
-use std::process::Command;
+use crate::update_summary::UpdateSummary;
+use crate::update_section::{self, UpdateSection};
 use std::thread;
 
 mod imp {
     use super::*;
-    use std::cell::Cell; // Moved here from top-level to keep Cell in scope
+    use std::cell::{Cell, RefCell}; // Moved here from top-level to keep Cell in scope
 
-    #[derive(Debug, gtk::CompositeTemplate)]
+    #[derive(Debug, gtk::CompositeTemplate, glib::Properties)]
+    #[properties(wrapper_type = super::UpdaterWindow)]
     #[template(resource = "/org/gnome/Example/window.ui")]
     pub struct UpdaterWindow {
         // Template widgets
         #[template_child]
         pub label: TemplateChild<gtk::Label>,
         #[template_child]
-        pub update_list: TemplateChild<gtk::ListBox>,
+        pub update_list: TemplateChild<gtk::Box>,
         #[template_child]
         pub updateall_button: TemplateChild<gtk::Button>,
         #[template_child]
         pub refresh_button: TemplateChild<gtk::Button>,
         #[template_child]
         pub clear_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub search_bar: TemplateChild<gtk::SearchBar>,
+        #[template_child]
+        pub search_entry: TemplateChild<gtk::SearchEntry>,
+        #[template_child]
+        pub sort_dropdown: TemplateChild<gtk::DropDown>,
+        #[template_child]
+        pub all_chip: TemplateChild<gtk::ToggleButton>,
+        #[template_child]
+        pub security_chip: TemplateChild<gtk::ToggleButton>,
+        #[template_child]
+        pub kernel_chip: TemplateChild<gtk::ToggleButton>,
+        #[template_child]
+        pub aur_chip: TemplateChild<gtk::ToggleButton>,
+        #[template_child]
+        pub prefetch_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub summary_bar: TemplateChild<gtk::Box>,
+        #[template_child]
+        pub summary_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub summary_details_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub reboot_revealer: TemplateChild<gtk::Revealer>,
+        #[template_child]
+        pub reboot_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub restart_services_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub restart_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub error_banner: TemplateChild<adw::Banner>,
+        #[template_child]
+        pub toast_overlay: TemplateChild<adw::ToastOverlay>,
+        #[template_child]
+        pub view_stack: TemplateChild<adw::ViewStack>,
+        #[template_child]
+        pub up_to_date_page: TemplateChild<adw::StatusPage>,
+        #[template_child]
+        pub error_page: TemplateChild<adw::StatusPage>,
+        #[template_child]
+        pub error_details_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub error_retry_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub offline_retry_button: TemplateChild<gtk::Button>,
         pub number: Cell<i32>,
-        pub updates_avaible: Cell<bool>,
+        // Only changed through `transition`, everything else follows its notify.
+        #[property(get)]
+        pub state: RefCell<AppState>,
+        pub state_export: std::cell::OnceCell<StateExport>,
         pub refresh_sender: std::cell::OnceCell<std::sync::mpsc::Sender<()>>,
+        pub items: RefCell<Vec<UpdateItem>>,
+        pub sort_mode: Cell<SortMode>,
+        pub filter_chip: Cell<FilterChip>,
+        pub filter: std::cell::OnceCell<gtk::CustomFilter>,
+        pub sorter: std::cell::OnceCell<gtk::CustomSorter>,
+        pub reboot_status: RefCell<RebootStatus>,
+        // What the last kernel, driver or boot loader upgrade left behind,
+        // restarting asks first while something blocks it.
+        pub boot_check: RefCell<BootCheck>,
+        // What a row or section button is installing and since when, for
+        // the boot check once it's done.
+        pub partial_update: RefCell<Option<(Vec<String>, std::time::SystemTime)>>,
+        pub settings: std::cell::OnceCell<gio::Settings>,
+        pub last_check: Cell<Option<std::time::Instant>>,
+        pub store: RefCell<StoredState>,
+        // The stale upgrade warning shows once per run.
+        pub stale_warned: Cell<bool>,
+        pub conditions: std::cell::OnceCell<Conditions>,
+        // pacman's output of the last failure, shown in the error details.
+        pub last_error: RefCell<String>,
     }
 
     impl Default for UpdaterWindow {
@@ -39,9 +128,47 @@ mod imp {
                 refresh_button: TemplateChild::default(),
                 clear_button: TemplateChild::default(),
                 update_list: TemplateChild::default(),
+                search_bar: TemplateChild::default(),
+                search_entry: TemplateChild::default(),
+                sort_dropdown: TemplateChild::default(),
+                all_chip: TemplateChild::default(),
+                security_chip: TemplateChild::default(),
+                kernel_chip: TemplateChild::default(),
+                aur_chip: TemplateChild::default(),
+                prefetch_label: TemplateChild::default(),
+                summary_bar: TemplateChild::default(),
+                summary_label: TemplateChild::default(),
+                summary_details_label: TemplateChild::default(),
+                reboot_revealer: TemplateChild::default(),
+                reboot_label: TemplateChild::default(),
+                restart_services_button: TemplateChild::default(),
+                restart_button: TemplateChild::default(),
+                error_banner: TemplateChild::default(),
+                toast_overlay: TemplateChild::default(),
+                view_stack: TemplateChild::default(),
+                up_to_date_page: TemplateChild::default(),
+                error_page: TemplateChild::default(),
+                error_details_button: TemplateChild::default(),
+                error_retry_button: TemplateChild::default(),
+                offline_retry_button: TemplateChild::default(),
                 number: Cell::new(0),
-                updates_avaible: Cell::new(false),
+                state: RefCell::new(AppState::default()),
+                state_export: std::cell::OnceCell::new(),
                 refresh_sender: std::cell::OnceCell::new(),
+                items: RefCell::new(Vec::new()),
+                sort_mode: Cell::new(SortMode::default()),
+                filter_chip: Cell::new(FilterChip::default()),
+                filter: std::cell::OnceCell::new(),
+                sorter: std::cell::OnceCell::new(),
+                reboot_status: RefCell::new(RebootStatus::default()),
+                boot_check: RefCell::new(BootCheck::default()),
+                partial_update: RefCell::new(None),
+                settings: std::cell::OnceCell::new(),
+                last_check: Cell::new(None),
+                store: RefCell::new(StoredState::default()),
+                stale_warned: Cell::new(false),
+                conditions: std::cell::OnceCell::new(),
+                last_error: RefCell::new(String::new()),
             }
         }
     }
@@ -61,6 +188,7 @@ mod imp {
         }
     }
     
+    #[glib::derived_properties]
     impl ObjectImpl for UpdaterWindow {
         fn constructed(&self) {
             self.parent_constructed();
@@ -68,10 +196,20 @@ mod imp {
 
             let (sender, receiver) = std::sync::mpsc::channel::<()>();
             self.refresh_sender.set(sender).expect("Sender already set");
+            self.settings.set(gio::Settings::new("org.gnome.Example")).expect("Settings already set");
+            self.conditions.set(Conditions::new(self.settings.get().unwrap())).expect("Conditions already set");
+            // Read once here, update_store() keeps it and the file in step.
+            let mut store = StoredState::load();
+            // Upgrades run from a terminal only show up in pacman's log.
+            if let Some(upgraded) = report::last_full_upgrade() {
+                store.last_upgrade = store.last_upgrade.max(upgraded);
+            }
+            self.store.replace(store);
 
-            glib::timeout_add_local(std::time::Duration::from_millis(500), glib::clone!(@weak obj => @default-return glib::ControlFlow::Break, move || {
-                if let Ok(_) = receiver.try_recv() {
-                    obj.check_for_updates(None);
+            glib::timeout_add_local(std::time::Duration::from_millis(500), glib::clone!(#[weak] obj, #[upgrade_or] glib::ControlFlow::Break, move || {
+                if receiver.try_recv().is_ok() {
+                    obj.check_for_updates();
+                    obj.check_reboot();
                 }
                 glib::ControlFlow::Continue
             }));
@@ -79,9 +217,18 @@ mod imp {
             // obj.check_sudo();
 
             obj.setup_css();
+            obj.setup_state();
             obj.setup_callbacks();
+            obj.setup_actions();
+            obj.setup_filtering();
+            obj.setup_background_check();
+            obj.setup_holds();
 
-            obj.check_for_updates(None);
+            obj.show_cached_updates();
+            obj.check_for_updates();
+            obj.check_reboot();
+            obj.show_offline_status();
+            obj.show_auto_update_report();
         }
     }
 
@@ -116,162 +263,1219 @@ impl UpdaterWindow {
         );
     }
 
+    fn setup_state(&self) {
+        self.connect_state_notify(|obj| obj.apply_state());
+
+        // A check that failed while offline is retried once the network is back.
+        gio::NetworkMonitor::default().connect_network_changed(glib::clone!(#[weak(rename_to = obj)] self, move |_, available| {
+            if available && matches!(obj.state(), AppState::Failed(_)) && obj.imp().items.borrow().is_empty() {
+                obj.check_for_updates();
+            }
+        }));
+
+        if let Some(application) = self.application() {
+            if let (Some(connection), Some(path)) = (application.dbus_connection(), application.dbus_object_path()) {
+                let export = StateExport::register(&connection, &path, glib::clone!(#[weak(rename_to = obj)] self, #[upgrade_or] AppState::default(), move || {
+                    obj.state()
+                }));
+                if let Some(export) = export {
+                    self.imp().state_export.set(export).expect("State export already set");
+                }
+            }
+        }
+
+        self.apply_state();
+    }
+
+    // Returns false when the event doesn't apply, e.g. a second check while
+    // one is running.
+    fn transition(&self, event: StateEvent) -> bool {
+        let Some(next) = self.state().next(event) else {
+            return false;
+        };
+        if next != self.state() {
+            if let AppState::Failed(reason) = &next {
+                self.update_store(|store| store.record_error(reason));
+            }
+            self.imp().state.replace(next);
+            self.notify_state();
+        }
+        true
+    }
+
+    fn apply_state(&self) {
+        let imp = self.imp();
+        let state = self.state();
+
+        imp.label.set_text(&state.label());
+        imp.updateall_button.set_sensitive(!state.is_busy());
+        imp.refresh_button.set_sensitive(!state.is_busy());
+
+        let page = match &state {
+            AppState::Idle | AppState::Checking if !imp.items.borrow().is_empty() => {
+                imp.label.set_text("Checking for updates, the list may be out of date");
+                "updates"
+            }
+            AppState::Idle | AppState::Checking => "checking",
+            AppState::UpToDate | AppState::RebootRequired => {
+                self.show_up_to_date(&state);
+                "up-to-date"
+            }
+            AppState::Failed(_) if !gio::NetworkMonitor::default().is_network_available() => "offline",
+            AppState::Failed(reason) => {
+                self.show_error_page(reason);
+                "error"
+            }
+            AppState::UpdatesAvailable(_) | AppState::Updating => "updates",
+        };
+        imp.view_stack.set_visible_child_name(page);
+
+        if let Some(export) = imp.state_export.get() {
+            export.changed(&state);
+        }
+    }
+
+    fn show_up_to_date(&self, state: &AppState) {
+        let page = &self.imp().up_to_date_page;
+        if *state == AppState::RebootRequired {
+            page.set_icon_name(Some("system-reboot-symbolic"));
+            page.set_title(&state.label());
+            page.set_description(None);
+            return;
+        }
+
+        page.set_icon_name(Some("emblem-ok-symbolic"));
+        page.set_title("System Up to Date");
+
+        let store = self.imp().store.borrow();
+        let mut lines = Vec::new();
+        if let Some(checked) = last_checked(store.last_check) {
+            lines.push(checked);
+        }
+        match store.days_since_upgrade() {
+            Some(0) => lines.push("Last updated today".to_string()),
+            Some(1) => lines.push("Last updated yesterday".to_string()),
+            Some(days) => lines.push(format!("Last updated {} days ago", days)),
+            None => {}
+        }
+        if let Some(error) = store.unresolved_error() {
+            lines.push(format!("The last attempt failed: {}", error));
+        }
+        page.set_description(Some(&lines.join("\n")));
+    }
+
+    fn update_store(&self, change: impl FnOnce(&mut StoredState)) {
+        let mut store = self.imp().store.borrow_mut();
+        change(&mut store);
+        store.save();
+    }
+
+    // Pending updates on a system that was not upgraded for a month most
+    // likely mean nobody is looking after it.
+    fn warn_stale_upgrade(&self) {
+        let imp = self.imp();
+        if imp.stale_warned.get() || !matches!(self.state(), AppState::UpdatesAvailable(_)) {
+            return;
+        }
+        let Some(days) = imp.store.borrow().days_since_upgrade() else {
+            return;
+        };
+        if imp.store.borrow().upgrade_is_stale() {
+            imp.stale_warned.set(true);
+            self.show_toast(&format!("The system was last updated {} days ago, install the pending updates soon", days));
+        }
+    }
+
+    // The page replaces the banner, it explains the same error.
+    fn show_error_page(&self, reason: &str) {
+        let imp = self.imp();
+        let log = imp.last_error.borrow().clone();
+
+        imp.error_banner.set_revealed(false);
+        imp.error_page.set_title(reason);
+        if log.is_empty() {
+            imp.error_page.set_description(Some("The details are in the log."));
+        } else {
+            imp.error_page.set_description(Some(&PacmanError::classify(&log).explanation()));
+        }
+        imp.error_details_button.set_visible(!log.is_empty());
+    }
+
+    fn show_toast(&self, text: &str) {
+        self.imp().toast_overlay.add_toast(adw::Toast::new(text));
+    }
+
+    // Runs once a check or the reboot analysis finished, while either is
+    // still running the state can't settle yet.
+    fn settle_reboot(&self) {
+        if offline_update::is_scheduled() {
+            self.transition(StateEvent::UpdateScheduled);
+        } else if self.imp().reboot_status.borrow().reboot_required() {
+            self.transition(StateEvent::RebootNeeded);
+        }
+    }
+
     fn setup_callbacks(&self) {
-        self.imp().clear_button.connect_clicked(glib::clone!(@weak self as obj => move |_| {
+        self.imp().clear_button.connect_clicked(glib::clone!(#[weak(rename_to = obj)] self, move |_| {
             obj.clear_list();
         }));
 
-        self.imp().refresh_button.connect_clicked(glib::clone!(@weak self as obj => move |_| {
-            obj.check_for_updates(None);
+        self.imp().refresh_button.connect_clicked(glib::clone!(#[weak(rename_to = obj)] self, move |_| {
+            obj.check_for_updates();
         }));
 
-        self.imp().updateall_button.connect_clicked(glib::clone!(@weak self as obj => move |_| {
+        self.imp().updateall_button.connect_clicked(glib::clone!(#[weak(rename_to = obj)] self, move |_| {
             obj.update_all();
         }));
+
+        self.imp().restart_button.connect_clicked(glib::clone!(#[weak(rename_to = obj)] self, move |_| {
+            obj.restart();
+        }));
+
+        self.imp().error_banner.connect_button_clicked(glib::clone!(#[weak(rename_to = obj)] self, move |_| {
+            obj.show_error_details();
+        }));
+
+        self.imp().restart_services_button.connect_clicked(glib::clone!(#[weak(rename_to = obj)] self, move |_| {
+            obj.restart_services();
+        }));
+
+        self.imp().error_details_button.connect_clicked(glib::clone!(#[weak(rename_to = obj)] self, move |_| {
+            obj.show_error_details();
+        }));
+
+        self.imp().error_retry_button.connect_clicked(glib::clone!(#[weak(rename_to = obj)] self, move |_| {
+            obj.update_all();
+        }));
+
+        self.imp().offline_retry_button.connect_clicked(glib::clone!(#[weak(rename_to = obj)] self, move |_| {
+            obj.check_for_updates();
+        }));
+    }
+
+    fn setup_actions(&self) {
+        let orphans_action = gio::ActionEntry::builder("orphans")
+            .activate(move |window: &Self, _, _| window.show_orphans())
+            .build();
+        let keyring_action = gio::ActionEntry::builder("repair-keyring")
+            .activate(move |window: &Self, _, _| window.show_keyring_repair())
+            .build();
+        let report_error_action = gio::ActionEntry::builder("report-error")
+            .parameter_type(Some(glib::VariantTy::STRING))
+            .activate(move |window: &Self, _, parameter| {
+                if let Some(log) = parameter.and_then(|parameter| parameter.get::<String>()) {
+                    window.report_error(&log);
+                }
+            })
+            .build();
+        // Also what the row buttons activate, and what tests/run-flows.sh
+        // calls over D-Bus.
+        let update_all_action = gio::ActionEntry::builder("update-all")
+            .activate(move |window: &Self, _, _| window.update_all())
+            .build();
+        let update_package_action = gio::ActionEntry::builder("update-package")
+            .parameter_type(Some(glib::VariantTy::STRING))
+            .activate(move |window: &Self, _, parameter| {
+                if let Some(package) = parameter.and_then(|parameter| parameter.get::<String>()) {
+                    window.update_package(&package);
+                }
+            })
+            .build();
+        let repos_action = gio::ActionEntry::builder("repos")
+            .activate(move |window: &Self, _, _| window.show_repos())
+            .build();
+        let search_action = gio::ActionEntry::builder("search")
+            .activate(move |window: &Self, _, _| {
+                let search_bar = &window.imp().search_bar;
+                search_bar.set_search_mode(!search_bar.is_search_mode());
+            })
+            .build();
+        let offline_action = gio::ActionEntry::builder("offline-update")
+            .activate(move |window: &Self, _, _| window.schedule_offline_update())
+            .build();
+        let cancel_offline_action = gio::ActionEntry::builder("cancel-offline-update")
+            .activate(move |window: &Self, _, _| window.cancel_offline_update())
+            .build();
+        let news_action = gio::ActionEntry::builder("news")
+            .activate(move |window: &Self, _, _| window.open_news())
+            .build();
+        let export_pending_action = gio::ActionEntry::builder("export-pending")
+            .activate(move |window: &Self, _, _| window.export_report(ReportKind::Pending))
+            .build();
+        let export_transaction_action = gio::ActionEntry::builder("export-transaction")
+            .activate(move |window: &Self, _, _| window.export_report(ReportKind::Transaction))
+            .build();
+        self.add_action_entries([orphans_action, repos_action, keyring_action, report_error_action, update_all_action, update_package_action, search_action, offline_action, cancel_offline_action, news_action, export_pending_action, export_transaction_action]);
+        self.add_action(&self.settings().create_action("prefetch-updates"));
+        self.add_action(&self.settings().create_action("allow-metered"));
+        self.add_action(&self.settings().create_action("allow-on-battery"));
+        self.add_action(&self.settings().create_action("auto-update"));
+    }
+
+    // Checks again once `check-interval` minutes passed since the last check.
+    fn setup_background_check(&self) {
+        glib::timeout_add_seconds_local(60, glib::clone!(#[weak(rename_to = obj)] self, #[upgrade_or] glib::ControlFlow::Break, move || {
+            let interval = obj.settings().uint("check-interval") as u64 * 60;
+            let due = obj.imp().last_check.get()
+                .map(|last| last.elapsed().as_secs() >= interval)
+                .unwrap_or(true);
+            if interval > 0 && due && !obj.state().is_busy() {
+                if let Some(deferral) = obj.conditions().deferral(Activity::Check) {
+                    obj.imp().prefetch_label.set_text(&format!("Background check deferred: {}", deferral.reason()));
+                } else {
+                    println!("Background check for updates");
+                    obj.check_for_updates();
+                }
+            }
+            obj.check_maintenance_window();
+            obj.show_auto_update_report();
+            glib::ControlFlow::Continue
+        }));
+    }
+
+    // The updates themselves are installed by updater-new-auto-update.timer,
+    // even while the app is closed. This only points out a window that can't
+    // ever open.
+    fn check_maintenance_window(&self) {
+        let settings = self.settings();
+        if settings.boolean("auto-update") && MaintenanceWindow::parse(&settings.string("maintenance-window")).is_none() {
+            self.imp().prefetch_label.set_text("Automatic updates are on, but the maintenance window is invalid");
+        }
+    }
+
+    // Shows the outcome of an automatic update that ran while nobody watched.
+    fn show_auto_update_report(&self) {
+        let Some(report) = auto_update::read_report() else {
+            return;
+        };
+        if report.finished <= self.settings().int64("auto-update-report-seen") {
+            return;
+        }
+        let _ = self.settings().set_int64("auto-update-report-seen", report.finished);
+        self.show_toast(&report.summary());
+        if report.skipped.is_some() {
+            return;
+        }
+        if report.success {
+            self.update_store(|store| store.record_upgrade(report.finished));
+        } else {
+            self.update_store(|store| store.record_error(&report.summary()));
+        }
+        // The system changed underneath the list.
+        if !self.state().is_busy() {
+            self.check_for_updates();
+        }
+        self.check_reboot();
+    }
+
+    fn open_news(&self) {
+        gtk::UriLauncher::new(auto_update::NEWS_PAGE).launch(Some(self), gio::Cancellable::NONE, |result| {
+            if let Err(e) = result {
+                eprintln!("Failed to open the Arch news: {}", e);
+            }
+        });
+
+        // Opening the news page counts as having read it.
+        let (sender, receiver) = std::sync::mpsc::channel::<Option<String>>();
+        UpdateManager::latest_news(sender);
+
+        glib::timeout_add_local(std::time::Duration::from_millis(100),
+            glib::clone!(#[weak(rename_to = obj)] self, #[upgrade_or] glib::ControlFlow::Break, move || {
+                if let Ok(latest) = receiver.try_recv() {
+                    if let Some(latest) = latest {
+                        let _ = obj.settings().set_string("news-last-read", &latest);
+                    }
+                    glib::ControlFlow::Break
+                } else {
+                    glib::ControlFlow::Continue
+                }
+            })
+        );
+    }
+
+    fn conditions(&self) -> &Conditions {
+        self.imp().conditions.get().unwrap()
+    }
+
+    fn refresh_prefetch(&self) {
+        let mut download = self.settings().boolean("prefetch-updates");
+        let deferred = if download { self.conditions().deferral(Activity::Download) } else { None };
+        if deferred.is_some() {
+            download = false;
+        }
+        if download {
+            self.imp().prefetch_label.set_text("Downloading updates in the background...");
+        }
+
+        let (sender, receiver) = std::sync::mpsc::channel::<PrefetchStatus>();
+        UpdateManager::prefetch(download, sender);
+
+        glib::timeout_add_local(std::time::Duration::from_millis(100),
+            glib::clone!(#[weak(rename_to = obj)] self, #[upgrade_or] glib::ControlFlow::Break, move || {
+                if let Ok(status) = receiver.try_recv() {
+                    obj.show_prefetch_status(status, deferred);
+                    glib::ControlFlow::Break
+                } else {
+                    glib::ControlFlow::Continue
+                }
+            })
+        );
+    }
+
+    fn show_prefetch_status(&self, status: PrefetchStatus, deferred: Option<Deferral>) {
+        let imp = self.imp();
+        for item in imp.items.borrow().iter() {
+            item.set_downloaded(status.downloaded.contains(&item.name()));
+        }
+        if let Some(throughput) = status.throughput {
+            self.update_store(|store| store.record_throughput(throughput));
+        }
+        self.update_summary();
+
+        let mut text = Vec::new();
+        if !status.downloaded.is_empty() {
+            text.push(format!(
+                "{} of {} updates downloaded, {} pre-fetched",
+                status.downloaded.len(),
+                imp.items.borrow().len(),
+                pacman::format_size(status.bytes),
+            ));
+        }
+        if let Some(deferral) = deferred {
+            text.push(format!("Background download deferred: {}", deferral.reason()));
+        }
+        imp.prefetch_label.set_text(&text.join(" · "));
+    }
+
+    fn settings(&self) -> &gio::Settings {
+        self.imp().settings.get().unwrap()
+    }
+
+    fn setup_filtering(&self) {
+        let imp = self.imp();
+
+        let filter = gtk::CustomFilter::new(glib::clone!(#[weak(rename_to = obj)] self, #[upgrade_or] true, move |item| {
+            let imp = obj.imp();
+            let item = item.downcast_ref::<UpdateItem>().unwrap();
+            item.matches(&imp.search_entry.text(), imp.filter_chip.get())
+        }));
+        let sorter = gtk::CustomSorter::new(glib::clone!(#[weak(rename_to = obj)] self, #[upgrade_or] gtk::Ordering::Equal, move |a, b| {
+            let a = a.downcast_ref::<UpdateItem>().unwrap();
+            let b = b.downcast_ref::<UpdateItem>().unwrap();
+            a.compare(b, obj.imp().sort_mode.get()).into()
+        }));
+        imp.filter.set(filter).unwrap();
+        imp.sorter.set(sorter).unwrap();
+
+        imp.search_bar.connect_entry(&*imp.search_entry);
+        imp.search_bar.set_key_capture_widget(Some(self));
+        imp.search_entry.connect_search_changed(glib::clone!(#[weak(rename_to = obj)] self, move |_| {
+            obj.refilter();
+        }));
+
+        imp.sort_dropdown.set_selected(1);
+        imp.sort_dropdown.connect_selected_notify(glib::clone!(#[weak(rename_to = obj)] self, move |dropdown| {
+            obj.imp().sort_mode.set(SortMode::from_index(dropdown.selected()));
+            obj.rebuild_sections();
+        }));
+
+        let chips = [
+            (imp.all_chip.get(), FilterChip::All),
+            (imp.security_chip.get(), FilterChip::Security),
+            (imp.kernel_chip.get(), FilterChip::KernelDriver),
+            (imp.aur_chip.get(), FilterChip::Aur),
+        ];
+        for (chip, filter_chip) in chips {
+            chip.connect_toggled(glib::clone!(#[weak(rename_to = obj)] self, move |chip| {
+                if chip.is_active() {
+                    obj.imp().filter_chip.set(filter_chip);
+                    obj.refilter();
+                }
+            }));
+        }
+    }
+
+    fn setup_holds(&self) {
+        for key in ["held-packages", "skipped-versions"] {
+            self.settings().connect_changed(Some(key), glib::clone!(#[weak(rename_to = obj)] self, move |_, _| {
+                obj.rebuild_sections();
+                // Held updates drop out of the totals.
+                obj.update_summary();
+            }));
+        }
+    }
+
+    fn held_packages(&self) -> Vec<String> {
+        self.imp().items.borrow()
+            .iter()
+            .filter(|item| item.held())
+            .map(|item| item.name())
+            .collect()
+    }
+
+    fn refilter(&self) {
+        if let Some(filter) = self.imp().filter.get() {
+            filter.changed(gtk::FilterChange::Different);
+        }
+    }
+
+    // Regroups the items in place, sections and rows that stay are kept.
+    // Updates are grouped by repository only when sorting by repository,
+    // every other sort mode shows one flat list.
+    fn rebuild_sections(&self) {
+        let imp = self.imp();
+        let filter = imp.filter.get().unwrap();
+        let sorter = imp.sorter.get().unwrap();
+        let grouped = imp.sort_mode.get() == SortMode::Repository;
+
+        let holds = Holds::load();
+        let (held, mut items): (Vec<UpdateItem>, Vec<UpdateItem>) = imp.items.borrow()
+            .iter()
+            .cloned()
+            .partition(|item| holds.is_held(&item.name(), &item.new_version()));
+        if grouped {
+            items.sort_by(|a, b| a.compare(b, SortMode::Repository));
+        }
+
+        // (repository of the section, its items) in display order
+        let mut layout: Vec<(Option<String>, Vec<UpdateItem>)> = Vec::new();
+        for item in items {
+            item.set_held(false);
+            let repo = grouped.then(|| item.repo());
+            match layout.last_mut() {
+                Some((last, group)) if *last == repo => group.push(item),
+                _ => layout.push((repo, vec![item])),
+            }
+        }
+        if !held.is_empty() {
+            for item in held.iter() {
+                item.set_held(true);
+            }
+            layout.push((Some(update_section::HELD.to_string()), held));
+        }
+
+        let mut existing = self.sections();
+        let mut previous: Option<UpdateSection> = None;
+        for (repo, group) in layout {
+            let section = match existing.iter().position(|section| section.repo() == repo) {
+                Some(index) => existing.remove(index),
+                None => {
+                    let sender = imp.refresh_sender.get().unwrap().clone();
+                    let section = UpdateSection::new(repo.as_deref(), filter, sorter, sender);
+                    imp.update_list.append(&section);
+                    section
+                }
+            };
+            section.sync_items(&group);
+            imp.update_list.reorder_child_after(&section, previous.as_ref());
+            previous = Some(section);
+        }
+        for section in existing {
+            imp.update_list.remove(&section);
+        }
+        // Kept items may have new sizes or severities, or the sort mode changed.
+        sorter.changed(gtk::SorterChange::Different);
+        filter.changed(gtk::FilterChange::Different);
+    }
+
+    // Items of updates that are still pending are reused, so are their rows.
+    fn set_updates(&self, updates: &[PendingUpdate]) {
+        let imp = self.imp();
+        let known = imp.items.take();
+        let items = updates
+            .iter()
+            .map(|update| {
+                known
+                    .iter()
+                    .find(|item| item.name() == update.name
+                        && item.old_version() == update.old_version
+                        && item.new_version() == update.new_version)
+                    .map(|item| {
+                        item.refresh(update);
+                        item.clone()
+                    })
+                    .unwrap_or_else(|| UpdateItem::new(update))
+            })
+            .collect();
+        imp.items.replace(items);
+        self.rebuild_sections();
+        self.update_summary();
+    }
+
+    fn update_summary(&self) {
+        let imp = self.imp();
+        let items = imp.items.borrow();
+        imp.summary_bar.set_visible(!items.is_empty());
+        let summary = UpdateSummary::new(&items, imp.store.borrow().average_throughput());
+        imp.summary_label.set_text(&summary.headline());
+        imp.summary_details_label.set_text(&summary.breakdown());
+    }
+
+    // Shows the list of the last run right away, marked as stale until the
+    // check that follows replaces it.
+    fn show_cached_updates(&self) {
+        let pending = self.imp().store.borrow().pending.clone();
+        if !pending.is_empty() {
+            self.set_updates(&pending);
+        }
+    }
+
+    fn export_report(&self, kind: ReportKind) {
+        if kind == ReportKind::Pending {
+            let pending: Vec<PendingUpdate> = self.imp().items.borrow().iter()
+                .map(|item| PendingUpdate {
+                    name: item.name(),
+                    old_version: item.old_version(),
+                    new_version: item.new_version(),
+                    repo: item.repo(),
+                    ..Default::default()
+                })
+                .collect();
+            self.save_report(Report::pending(&pending));
+            return;
+        }
+
+        let (sender, receiver) = std::sync::mpsc::channel::<Option<Report>>();
+        UpdateManager::last_transaction(sender);
+
+        glib::timeout_add_local(std::time::Duration::from_millis(100),
+            glib::clone!(#[weak(rename_to = obj)] self, #[upgrade_or] glib::ControlFlow::Break, move || {
+                if let Ok(report) = receiver.try_recv() {
+                    match report {
+                        Some(report) => obj.save_report(report),
+                        None => obj.show_toast("No transaction found in pacman's log"),
+                    }
+                    glib::ControlFlow::Break
+                } else {
+                    glib::ControlFlow::Continue
+                }
+            })
+        );
+    }
+
+    // The format follows the extension of the chosen file.
+    fn save_report(&self, report: Report) {
+        let filters = gio::ListStore::new::<gtk::FileFilter>();
+        for (name, format) in [("JSON", ReportFormat::Json), ("Markdown", ReportFormat::Markdown), ("Plain Text", ReportFormat::Text)] {
+            let filter = gtk::FileFilter::new();
+            filter.set_name(Some(name));
+            filter.add_suffix(format.extension());
+            filters.append(&filter);
+        }
+
+        let dialog = gtk::FileDialog::builder()
+            .title("Export Report")
+            .initial_name(report.file_name(ReportFormat::Json))
+            .filters(&filters)
+            .modal(true)
+            .build();
+
+        dialog.save(Some(self), gio::Cancellable::NONE, glib::clone!(#[weak(rename_to = obj)] self, move |result| {
+            // Dismissing the dialog is an error too.
+            let Some(path) = result.ok().and_then(|file| file.path()) else {
+                return;
+            };
+            match std::fs::write(&path, report.render(ReportFormat::from_path(&path))) {
+                Ok(()) => obj.show_toast(&format!("Report saved to {}", path.display())),
+                Err(e) => {
+                    eprintln!("Failed to write {}: {}", path.display(), e);
+                    obj.show_toast("Saving the report failed, read the log");
+                }
+            }
+        }));
+    }
+
+    fn show_orphans(&self) {
+        let dialog = OrphansDialog::new();
+        dialog.present(Some(self));
+    }
+
+    fn show_repos(&self) {
+        let dialog = ReposDialog::new();
+        dialog.present(Some(self));
     }
 
     fn clear_list(&self) {
         let imp = self.imp();
+        imp.items.borrow_mut().clear();
         while let Some(child) = imp.update_list.first_child() {
             imp.update_list.remove(&child);
         }
+        self.update_summary();
     }
 
 
     fn update_all(&self) {
         let imp = self.imp();
 
-        if imp.updates_avaible.get() {
-
-            
-            imp.label.set_text("Updating All...");
-            self.disable_all_row_buttons();
-            imp.updateall_button.set_sensitive(false);
-            println!("thread started for updating all");
-            
-            let (sender, receiver) = std::sync::mpsc::channel();
-            
-            thread::spawn(move || {
-                match Command::new("pkexec")
-                    .args(["pacman", "-Syu", "--noconfirm"])
-                    .output()
-                {
-                    Ok(output) => {
-
-                        if output.status.success() {
-                            println!("all packages updating");
-                            println!("Status: {}", output.status);
-                            println!("Stdout: {}", String::from_utf8_lossy(&output.stdout));
-                            println!("Stderr: {}", String::from_utf8_lossy(&output.stderr));
-                            sender.send(true).unwrap();
-
-                    }    else {
-                            eprintln!("Command failed to execute");
-                            sender.send(false).unwrap();
-                        }
+        match self.state() {
+            AppState::UpdatesAvailable(_) => self.check_disk_space(),
+            // Retrying after a failure, the list is still the one that failed.
+            AppState::Failed(_) if !imp.items.borrow().is_empty() => self.check_disk_space(),
+            _ => {
+                println!("System is up to date");
+                self.check_for_updates();
+            }
+        }
+    }
+
+    // Runs before every full upgrade, a transaction that fills a partition
+    // halfway through can leave the system unbootable.
+    fn check_disk_space(&self) {
+        let imp = self.imp();
+        imp.label.set_text("Checking free disk space...");
+        imp.updateall_button.set_sensitive(false);
+
+        let held = self.held_packages();
+        let pending: Vec<PendingUpdate> = imp.items.borrow().iter()
+            .filter(|item| !held.contains(&item.name()))
+            .map(|item| PendingUpdate {
+                name: item.name(),
+                // Prefetched packages are already in a cache directory.
+                download_size: if item.downloaded() { 0 } else { item.download_size() },
+                installed_delta: item.installed_delta(),
+                ..Default::default()
+            })
+            .collect();
+
+        let (sender, receiver) = std::sync::mpsc::channel::<Vec<Shortage>>();
+        UpdateManager::check_disk_space(pending, sender);
+
+        glib::timeout_add_local(std::time::Duration::from_millis(100),
+            glib::clone!(#[weak(rename_to = obj)] self, #[upgrade_or] glib::ControlFlow::Break, move || {
+                if let Ok(shortages) = receiver.try_recv() {
+                    if shortages.is_empty() {
+                        obj.check_keyring();
+                    } else {
+                        obj.imp().label.set_text(&obj.state().label());
+                        obj.imp().updateall_button.set_sensitive(true);
+                        obj.show_disk_space(&shortages);
                     }
-                    Err(e) => {
-                        eprintln!("Command failed to execute: {}", e);
-                        sender.send(false).unwrap();
+                    glib::ControlFlow::Break
+                } else {
+                    glib::ControlFlow::Continue
+                }
+            })
+        );
+    }
+
+    fn show_disk_space(&self, shortages: &[Shortage]) {
+        let details: Vec<String> = shortages.iter().map(|shortage| format!("• {}", shortage.describe())).collect();
+        let dialog = adw::AlertDialog::builder()
+            .heading("Not Enough Disk Space")
+            .body(format!("The update was not started:\n\n{}\n\n\
+                   Cleaning the package cache keeps only the newest version of each installed package. \
+                   If that is not enough, free some space by hand and try again.", details.join("\n")))
+            .close_response("cancel")
+            .default_response("clean-cache")
+            .build();
+        dialog.add_response("cancel", "Cancel");
+        dialog.add_response("clean-cache", "Clean Cache");
+        dialog.set_response_appearance("clean-cache", adw::ResponseAppearance::Suggested);
+
+        dialog.connect_response(None, glib::clone!(#[weak(rename_to = obj)] self, move |_, response| {
+            if response == "clean-cache" {
+                obj.clean_cache();
+            }
+        }));
+
+        dialog.present(Some(self));
+    }
+
+    fn clean_cache(&self) {
+        let imp = self.imp();
+        imp.label.set_text("Cleaning the package cache...");
+        imp.updateall_button.set_sensitive(false);
+
+        let (sender, receiver) = std::sync::mpsc::channel::<String>();
+        UpdateManager::clean_cache(sender);
+
+        glib::timeout_add_local(std::time::Duration::from_millis(100),
+            glib::clone!(#[weak(rename_to = obj)] self, #[upgrade_or] glib::ControlFlow::Break, move || {
+                if let Ok(status) = receiver.try_recv() {
+                    let imp = obj.imp();
+                    imp.updateall_button.set_sensitive(true);
+                    imp.label.set_text(&obj.state().label());
+                    if status == "Ok" {
+                        obj.show_toast("Package cache cleaned, try the update again");
+                    } else {
+                        obj.show_toast("Cleaning the package cache failed");
                     }
+                    glib::ControlFlow::Break
+                } else {
+                    glib::ControlFlow::Continue
                 }
-            });
-            
-            glib::timeout_add_local(std::time::Duration::from_millis(100), 
-                glib::clone!(@weak self as obj => @default-return glib::ControlFlow::Break, move || {
-                    if let Ok(success) = receiver.try_recv() {
-                        if success {
-                            obj.success_update();
-                        } else {
-                            obj.failed_update();
+            })
+        );
+    }
+
+    // Packages signed with keys newer than the keyring fail to verify, so an
+    // outdated keyring is installed ahead of everything else.
+    fn check_keyring(&self) {
+        self.imp().label.set_text("Checking the keyring...");
+
+        let (sender, receiver) = std::sync::mpsc::channel::<KeyringState>();
+        UpdateManager::check_keyring(sender);
+
+        glib::timeout_add_local(std::time::Duration::from_millis(100),
+            glib::clone!(#[weak(rename_to = obj)] self, #[upgrade_or] glib::ControlFlow::Break, move || {
+                if let Ok(state) = receiver.try_recv() {
+                    let held = obj.held_packages().iter().any(|name| name == keyring::KEYRING_PACKAGE);
+                    let notice = match &state {
+                        KeyringState::Current => None,
+                        KeyringState::Outdated { installed, available } => {
+                            Some(format!("archlinux-keyring {} is out of date, {} is installed first", installed, available))
                         }
-                        glib::ControlFlow::Break
-                    } else {
-                        glib::ControlFlow::Continue
+                        KeyringState::Old { days } => {
+                            Some(format!("archlinux-keyring was last updated {} days ago, it is refreshed first", days))
+                        }
+                    };
+                    match notice {
+                        Some(_) if held => obj.show_toast("archlinux-keyring is out of date but held, signature checks may fail"),
+                        Some(notice) => obj.show_toast(&notice),
+                        None => {}
                     }
-                })
-            );    
+                    obj.start_update_all(state != KeyringState::Current && !held);
+                    glib::ControlFlow::Break
+                } else {
+                    glib::ControlFlow::Continue
+                }
+            })
+        );
+    }
+
+    fn start_update_all(&self, keyring_first: bool) {
+        let imp = self.imp();
+
+        if !self.transition(StateEvent::UpdateStarted) {
+            return;
         }
+        imp.error_banner.set_revealed(false);
+        imp.boot_check.replace(BootCheck::default());
+        self.disable_all_row_buttons();
+        println!("thread started for updating all");
         
-        else {
-            println!("System is up to date");
-            imp.label.set_text("System is up to date, checking for new updates...");
-            self.check_for_updates(None);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let ignored = self.held_packages();
+        // Held packages are left out here, or a held keyring would still be
+        // installed by the keyring step ahead of the upgrade.
+        let mut pending: Vec<String> = imp.items.borrow()
+            .iter()
+            .map(|item| item.name())
+            .filter(|name| !ignored.contains(name))
+            .collect();
+        if keyring_first && !pending.iter().any(|name| name == keyring::KEYRING_PACKAGE) {
+            pending.push(keyring::KEYRING_PACKAGE.to_string());
         }
+        let upgraded = pending.clone();
+        let started = std::time::SystemTime::now();
+        
+        thread::spawn(move || {
+            // pacman's questions come back through `sender` as well.
+            let command = UpdateManager::upgrade_command(&pending, &ignored);
+            let result = transaction::run(command, &sender);
+            if result.is_ok() {
+                println!("all packages updating");
+            }
+            let _ = sender.send(TransactionEvent::Finished(result));
+        });
+        
+        glib::timeout_add_local(std::time::Duration::from_millis(100), 
+            glib::clone!(#[weak(rename_to = obj)] self, #[upgrade_or] glib::ControlFlow::Break, move || {
+                if let Ok(event) = receiver.try_recv() {
+                    match event {
+                        TransactionEvent::Question(question, reply) => {
+                            question_dialog::present(&obj, question, reply);
+                            return glib::ControlFlow::Continue;
+                        }
+                        TransactionEvent::Progress(percent, text) => {
+                            obj.imp().label.set_text(&format!("{} ({}%)", text, percent));
+                            return glib::ControlFlow::Continue;
+                        }
+                        TransactionEvent::Downloaded(time) => {
+                            obj.record_download(&upgraded, time);
+                            return glib::ControlFlow::Continue;
+                        }
+                        TransactionEvent::Finished(Ok(output)) => {
+                            obj.success_update();
+                            if boot_check::needs_check(&upgraded) {
+                                obj.check_boot(upgraded.clone(), output, started);
+                            }
+                        }
+                        TransactionEvent::Finished(Err(error)) => obj.failed_update(&error),
+                    }
+                    glib::ControlFlow::Break
+                } else {
+                    glib::ControlFlow::Continue
+                }
+            })
+        );    
     }
 
     fn success_update(&self) {
         let imp = self.imp();
-        imp.label.set_text("Update Successful");
-        imp.updateall_button.set_sensitive(true);
-        self.clear_list();
+        imp.error_banner.set_revealed(false);
+        self.update_store(|store| store.record_upgrade(glib::real_time() / 1_000_000));
+        UpdateManager::report_upgrade();
+        self.transition(StateEvent::UpdateFinished);
+        self.show_toast("Update successful");
+        // pacman -Syu leaves AUR and Flatpak updates alone, the check tells
+        // what is still pending instead of assuming nothing is.
+        self.check_for_updates();
+        self.check_reboot();
     }
 
-    fn failed_update(&self) {
+    fn schedule_offline_update(&self) {
+        if !self.transition(StateEvent::UpdateStarted) {
+            return;
+        }
+        self.imp().label.set_text("Downloading updates for the next restart...");
+        self.disable_all_row_buttons();
+
+        let (sender, receiver) = std::sync::mpsc::channel::<String>();
+        UpdateManager::schedule_offline_update(self.held_packages(), sender);
+
+        glib::timeout_add_local(std::time::Duration::from_millis(100),
+            glib::clone!(#[weak(rename_to = obj)] self, #[upgrade_or] glib::ControlFlow::Break, move || {
+                if let Ok(status) = receiver.try_recv() {
+                    if status == "Ok" {
+                        obj.transition(StateEvent::UpdateFinished);
+                        obj.show_offline_status();
+                    } else {
+                        obj.imp().last_error.replace(String::new());
+                        obj.transition(StateEvent::UpdateFailed("Preparing the update failed, read the log".to_string()));
+                    }
+                    glib::ControlFlow::Break
+                } else {
+                    glib::ControlFlow::Continue
+                }
+            })
+        );
+    }
+
+    fn cancel_offline_update(&self) {
+        let (sender, receiver) = std::sync::mpsc::channel::<String>();
+        UpdateManager::cancel_offline_update(sender);
+
+        glib::timeout_add_local(std::time::Duration::from_millis(100),
+            glib::clone!(#[weak(rename_to = obj)] self, #[upgrade_or] glib::ControlFlow::Break, move || {
+                if let Ok(status) = receiver.try_recv() {
+                    if status == "Ok" {
+                        // Drops RebootRequired again, the packages are still pending.
+                        obj.check_for_updates();
+                        obj.show_toast("Update on restart cancelled");
+                        obj.check_reboot();
+                    }
+                    glib::ControlFlow::Break
+                } else {
+                    glib::ControlFlow::Continue
+                }
+            })
+        );
+    }
+
+    // Reports a pending offline update, or the outcome of the one applied
+    // during the last boot if the user hasn't seen it yet.
+    fn show_offline_status(&self) {
         let imp = self.imp();
-        imp.label.set_text("Update Failed, read the log");
-        imp.updateall_button.set_sensitive(true);
-        self.check_for_updates(Some(true));
+
+        if offline_update::is_scheduled() {
+            self.settle_reboot();
+            imp.reboot_label.set_text("Updates are downloaded and will be installed while restarting.");
+            imp.restart_services_button.set_visible(false);
+            imp.reboot_revealer.set_reveal_child(true);
+            return;
+        }
+
+        let Some(result) = offline_update::read_result() else {
+            return;
+        };
+        if result.finished <= self.settings().int64("offline-result-seen") {
+            return;
+        }
+        let _ = self.settings().set_int64("offline-result-seen", result.finished);
+
+        UpdateManager::report_upgrade();
+        if result.success {
+            self.update_store(|store| store.record_upgrade(result.finished));
+            self.show_toast(&format!("{} updates were installed during the last restart", result.packages.len()));
+        } else {
+            self.show_toast(&format!("Installing updates during the last restart failed, see {}", offline_update::LOG_FILE));
+        }
     }
 
-    fn disable_all_row_buttons(&self) {
+    fn check_reboot(&self) {
+        let (sender, receiver) = std::sync::mpsc::channel::<RebootStatus>();
+        UpdateManager::check_reboot(sender);
+
+        glib::timeout_add_local(std::time::Duration::from_millis(100),
+            glib::clone!(#[weak(rename_to = obj)] self, #[upgrade_or] glib::ControlFlow::Break, move || {
+                if let Ok(status) = receiver.try_recv() {
+                    obj.show_reboot_status(status);
+                    glib::ControlFlow::Break
+                } else {
+                    glib::ControlFlow::Continue
+                }
+            })
+        );
+    }
+
+    fn check_boot(&self, upgraded: Vec<String>, output: String, started: std::time::SystemTime) {
+        let (sender, receiver) = std::sync::mpsc::channel::<BootCheck>();
+        UpdateManager::check_boot(upgraded, output, started, sender);
+
+        glib::timeout_add_local(std::time::Duration::from_millis(100),
+            glib::clone!(#[weak(rename_to = obj)] self, #[upgrade_or] glib::ControlFlow::Break, move || {
+                if let Ok(check) = receiver.try_recv() {
+                    let clean = check.issues.is_empty();
+                    let verified = check.verified.join(", ");
+                    obj.imp().boot_check.replace(check);
+                    let status = obj.imp().reboot_status.borrow().clone();
+                    obj.show_reboot_status(status);
+                    if !clean {
+                        obj.show_boot_issues(false);
+                    } else if !verified.is_empty() {
+                        obj.show_toast(&format!("Boot setup verified: {}", verified));
+                    }
+                    glib::ControlFlow::Break
+                } else {
+                    glib::ControlFlow::Continue
+                }
+            })
+        );
+    }
+
+    // With `restarting` the dialog stands between the user and the reboot.
+    fn show_boot_issues(&self, restarting: bool) {
+        let check = self.imp().boot_check.borrow().clone();
+        let (heading, intro) = if check.blocks_reboot() {
+            ("The System May Not Boot",
+             "The update finished, but something it should have done for the next boot is missing. Fix it before restarting:")
+        } else {
+            ("Check the Boot Setup", "The update finished, the boot setup may need attention:")
+        };
+        let dialog = adw::AlertDialog::builder()
+            .heading(heading)
+            .body(format!("{}\n\n{}", intro, boot_check::describe(&check)))
+            .close_response("close")
+            .default_response("close")
+            .build();
+        dialog.add_response("close", if restarting { "Cancel" } else { "Close" });
+        if restarting {
+            dialog.add_response("restart", "Restart Anyway");
+            dialog.set_response_appearance("restart", adw::ResponseAppearance::Destructive);
+            dialog.connect_response(None, |_, response| {
+                if response == "restart" {
+                    reboot::reboot();
+                }
+            });
+        }
+
+        dialog.present(Some(self));
+    }
+
+    fn restart(&self) {
+        if !self.imp().boot_check.borrow().blocks_reboot() {
+            reboot::reboot();
+        } else {
+            self.show_boot_issues(true);
+        }
+    }
+
+    fn show_reboot_status(&self, status: RebootStatus) {
         let imp = self.imp();
-        let mut child = imp.update_list.first_child();
-        
-        while let Some(row) = child {
-            if let Some(box_widget) = row.first_child() {
-                let mut button_child = box_widget.first_child();
-                while let Some(widget) = button_child {
-                    if let Ok(button) = widget.clone().downcast::<gtk::Button>() {
-                        button.set_sensitive(false);
+
+        let mut lines: Vec<String> = imp.boot_check.borrow().issues
+            .iter()
+            .map(|issue| if issue.blocking {
+                format!("Do not restart yet: {}.", issue.title)
+            } else {
+                format!("{}.", issue.title)
+            })
+            .collect();
+        if let Some(release) = status.outdated_kernel.as_ref() {
+            lines.push(format!("The running kernel ({}) was upgraded, restart to use the new one.", release));
+        }
+        if !status.user_services.is_empty() {
+            lines.push(format!("Services using outdated libraries: {}", status.user_services.join(", ")));
+        }
+        if !status.session_services.is_empty() {
+            lines.push(format!("Log out and back in to refresh: {}", status.session_services.join(", ")));
+        }
+        if !status.processes.is_empty() {
+            lines.push(format!("Applications using outdated libraries: {}", status.processes.join(", ")));
+        }
+        // Replaced libraries showed up in what could be read, system services
+        // likely use them too.
+        if status.system_unchecked && !status.is_clean() {
+            lines.push("System services could not be checked, restart the computer to be sure they use the new libraries.".to_string());
+        }
+
+        imp.reboot_label.set_text(&lines.join("\n"));
+        imp.restart_services_button.set_visible(status.restart_required());
+        imp.restart_services_button.set_sensitive(true);
+        imp.reboot_revealer.set_reveal_child(!status.is_clean() || !imp.boot_check.borrow().issues.is_empty());
+        imp.reboot_status.replace(status);
+        self.settle_reboot();
+    }
+
+    fn restart_services(&self) {
+        let imp = self.imp();
+        let status = imp.reboot_status.borrow().clone();
+        imp.restart_services_button.set_sensitive(false);
+
+        let (sender, receiver) = std::sync::mpsc::channel::<String>();
+        UpdateManager::restart_services(status.user_services, sender);
+
+        glib::timeout_add_local(std::time::Duration::from_millis(100),
+            glib::clone!(#[weak(rename_to = obj)] self, #[upgrade_or] glib::ControlFlow::Break, move || {
+                if let Ok(result) = receiver.try_recv() {
+                    if result != "Ok" {
+                        obj.show_toast("Restarting services failed, read the log");
                     }
-                    button_child = widget.next_sibling();
+                    obj.check_reboot();
+                    glib::ControlFlow::Break
+                } else {
+                    glib::ControlFlow::Continue
                 }
+            })
+        );
+    }
+
+    fn failed_update(&self, log: &str) {
+        let reason = format!("Update Failed, {}", PacmanError::classify(log).title().to_lowercase());
+        // Before the transition, the error page explains this log.
+        self.report_error(log);
+        UpdateManager::report_upgrade();
+        self.transition(StateEvent::UpdateFailed(reason));
+    }
+
+    fn report_error(&self, log: &str) {
+        let imp = self.imp();
+        let error = PacmanError::classify(log);
+        imp.last_error.replace(log.to_string());
+        imp.error_banner.set_title(&error.title());
+        imp.error_banner.set_revealed(true);
+    }
+
+    fn show_error_details(&self) {
+        let log = self.imp().last_error.borrow().clone();
+        let error = PacmanError::classify(&log);
+
+        let log_view = gtk::TextView::builder()
+            .editable(false)
+            .monospace(true)
+            .wrap_mode(gtk::WrapMode::WordChar)
+            .build();
+        log_view.buffer().set_text(log.trim());
+        let scrolled = gtk::ScrolledWindow::builder()
+            .min_content_height(160)
+            .child(&log_view)
+            .build();
+        let expander = gtk::Expander::builder()
+            .label("pacman Output")
+            .child(&scrolled)
+            .build();
+
+        let dialog = adw::AlertDialog::builder()
+            .heading(error.title())
+            .body(error.explanation())
+            .extra_child(&expander)
+            .close_response("close")
+            .default_response("close")
+            .build();
+        dialog.add_response("close", "Close");
+        for action in error.actions() {
+            dialog.add_response(action.id(), action.label());
+        }
+        if let Some(action) = error.actions().first() {
+            dialog.set_response_appearance(action.id(), adw::ResponseAppearance::Suggested);
+            dialog.set_default_response(Some(action.id()));
+        }
+
+        dialog.connect_response(None, glib::clone!(#[weak(rename_to = obj)] self, move |_, response| {
+            if let Some(action) = ErrorAction::from_id(response) {
+                obj.run_error_action(action);
             }
-            child = row.next_sibling();
+        }));
+
+        dialog.present(Some(self));
+    }
+
+    fn run_error_action(&self, action: ErrorAction) {
+        self.imp().error_banner.set_revealed(false);
+        match action {
+            ErrorAction::Retry => self.update_all(),
+            ErrorAction::RepairKeyring => self.show_keyring_repair(),
+            ErrorAction::EditRepositories => self.show_repos(),
+            ErrorAction::RemoveLock => self.remove_lock(),
+            ErrorAction::CleanCache => self.clean_cache(),
         }
     }
 
-    fn check_sudo(&self) {
-        match Command::new("sudo")
-        .args(["echo", "hi"])
-        .output()
-        {
-            Ok(_) => {
-                println!("hihihih");
-            }
+    fn remove_lock(&self) {
+        let (sender, receiver) = std::sync::mpsc::channel::<String>();
+        UpdateManager::remove_lock(sender);
 
-            Err(e) => {
-                self.close()
+        glib::timeout_add_local(std::time::Duration::from_millis(100),
+            glib::clone!(#[weak(rename_to = obj)] self, #[upgrade_or] glib::ControlFlow::Break, move || {
+                if let Ok(status) = receiver.try_recv() {
+                    if status == "Ok" {
+                        obj.show_toast("Lock removed, try the update again");
+                    } else {
+                        obj.show_toast("The lock is still in use, wait for the other package manager");
+                    }
+                    glib::ControlFlow::Break
+                } else {
+                    glib::ControlFlow::Continue
+                }
+            })
+        );
+    }
+
+    fn show_keyring_repair(&self) {
+        let dialog = adw::AlertDialog::builder()
+            .heading("Package Signatures Could Not Be Verified")
+            .body("This usually means the Arch Linux keyring is out of date. Update the keyring first, \
+                   then try the update again. If that is not enough, reset the keys from the installed \
+                   keyring or refresh them from the key servers, which can take several minutes.")
+            .close_response("cancel")
+            .default_response("keyring")
+            .build();
+        dialog.add_response("cancel", "Cancel");
+        dialog.add_response("refresh", "Refresh Keys");
+        dialog.add_response("populate", "Reset Keys");
+        dialog.add_response("keyring", "Update Keyring");
+        dialog.set_response_appearance("keyring", adw::ResponseAppearance::Suggested);
+
+        dialog.connect_response(None, glib::clone!(#[weak(rename_to = obj)] self, move |_, response| {
+            if let Some(fix) = KeyringFix::from_response(response) {
+                obj.repair_keyring(fix);
             }
+        }));
 
-        } 
+        dialog.present(Some(self));
     }
 
-    fn check_for_updates(&self, flag: Option<bool>) {
+    fn repair_keyring(&self, fix: KeyringFix) {
         let imp = self.imp();
-        let updlabel = flag.unwrap_or(true);
-   
-        
-        
-        self.clear_list();
-        
-        if updlabel == false {
-            imp.label.set_text("Checking...");
-        }
+        imp.label.set_text("Repairing the keyring...");
+        imp.updateall_button.set_sensitive(false);
 
         let (sender, receiver) = std::sync::mpsc::channel::<String>();
-        UpdateManager::check_updates(sender);
-        
+        UpdateManager::repair_keyring(fix, sender);
+
         glib::timeout_add_local(std::time::Duration::from_millis(100),
-            glib::clone!(@weak self as obj => @default-return glib::ControlFlow::Break, move || {
-                if let Ok(result_string) = receiver.try_recv() {
-                    if updlabel == true {
-                        obj.handle_update_result(result_string, Some(false));
+            glib::clone!(#[weak(rename_to = obj)] self, #[upgrade_or] glib::ControlFlow::Break, move || {
+                if let Ok(status) = receiver.try_recv() {
+                    let imp = obj.imp();
+                    imp.updateall_button.set_sensitive(true);
+                    imp.label.set_text(&obj.state().label());
+                    if status == "Ok" {
+                        obj.show_toast("Keyring repaired, try the update again");
                     } else {
-                        obj.handle_update_result(result_string, Some(true));
+                        obj.show_toast("Keyring repair failed, read the log");
                     }
-                    
                     glib::ControlFlow::Break
                 } else {
                     glib::ControlFlow::Continue
@@ -280,43 +1484,143 @@ impl UpdaterWindow {
         );
     }
 
-    fn handle_update_result(&self, result_string: String, flag: Option<bool>) {
+    // Rows and sections install part of the list on their own, the state
+    // machine still decides whether that can start now.
+    pub fn start_partial_update(&self, packages: &[String]) -> bool {
         let imp = self.imp();
-        let option = flag.unwrap_or(true);
+        if !self.transition(StateEvent::UpdateStarted) {
+            self.show_toast("Wait for the running check or update to finish");
+            return false;
+        }
+        imp.error_banner.set_revealed(false);
+        imp.boot_check.replace(BootCheck::default());
+        imp.partial_update.replace(Some((packages.to_vec(), std::time::SystemTime::now())));
+        self.disable_all_row_buttons();
+        true
+    }
 
-        if result_string.trim().is_empty() {
-            if option == true {
-                imp.label.set_text("System up to date");
+    // The rows ask for the list to be checked again afterwards.
+    pub fn finish_partial_update(&self, result: &Result<String, String>) {
+        for section in self.sections() {
+            section.set_buttons_sensitive(true);
+        }
+        let partial_update = self.imp().partial_update.take();
+        match result {
+            Ok(output) => {
+                self.transition(StateEvent::UpdateFinished);
+                // A kernel, driver or boot loader updated on its own needs
+                // the same check as one updated with everything else.
+                if let Some((packages, started)) = partial_update {
+                    if boot_check::needs_check(&packages) {
+                        self.check_boot(packages, output.clone(), started);
+                    }
+                }
             }
+            Err(log) => self.failed_update(log),
+        }
+    }
 
-            imp.updates_avaible.set(false);
-        } else {
-            if option == true {
-                imp.label.set_text("Updates found!");
+    // Feeds the time estimate of the summary, the prefetch isn't the only
+    // download that can be measured.
+    pub fn record_download(&self, packages: &[String], time: std::time::Duration) {
+        let bytes: u64 = self.imp().items.borrow()
+            .iter()
+            .filter(|item| !item.downloaded() && packages.contains(&item.name()))
+            .map(|item| item.download_size())
+            .sum();
+        if let Some(throughput) = prefetch::throughput(bytes, time) {
+            self.update_store(|store| store.record_throughput(throughput));
+        }
+    }
+
+    fn update_package(&self, package: &str) {
+        match self.sections().iter().find_map(|section| section.row(package)) {
+            Some(row) => row.install(),
+            None => eprintln!("No pending update for {}", package),
+        }
+    }
+
+    fn disable_all_row_buttons(&self) {
+        for section in self.sections() {
+            section.set_buttons_sensitive(false);
+        }
+    }
+
+    fn sections(&self) -> Vec<UpdateSection> {
+        let imp = self.imp();
+        let mut sections = Vec::new();
+        let mut child = imp.update_list.first_child();
+
+        while let Some(widget) = child {
+            if let Ok(section) = widget.clone().downcast::<UpdateSection>() {
+                sections.push(section);
             }
-           
-            imp.updates_avaible.set(true);
-            
-            for line in result_string.lines() {
-                if !line.trim().is_empty() {
-                    let mut parts = line.split(' ');
-                    let package = parts.next().unwrap_or("");
-                    let version = parts.next_back().unwrap_or("");
-
-                    if !package.is_empty() {
-                        let sender = imp.refresh_sender.get().unwrap().clone();
-                        let row = UpdateRow::new(package, version, sender);
-                        imp.update_list.append(&row);
-
-                        if let Some(row) = imp.update_list.last_child().and_downcast::<gtk::ListBoxRow>() {
-                            row.set_activatable(false);
-                            row.set_selectable(false);
-                            row.add_css_class("non-selectable-item");
-                        }
-                    }
+            child = widget.next_sibling();
+        }
+        sections
+    }
+
+    fn check_for_updates(&self) {
+        let imp = self.imp();
+
+        if !self.transition(StateEvent::CheckStarted) {
+            return;
+        }
+        imp.last_check.set(Some(std::time::Instant::now()));
+        imp.prefetch_label.set_text("");
+        if !imp.items.borrow().is_empty() {
+            imp.update_list.add_css_class("stale");
+        }
+
+        let (sender, receiver) = std::sync::mpsc::channel::<Result<Vec<PendingUpdate>, String>>();
+        UpdateManager::check_updates(sender);
+        
+        glib::timeout_add_local(std::time::Duration::from_millis(100),
+            glib::clone!(#[weak(rename_to = obj)] self, #[upgrade_or] glib::ControlFlow::Break, move || {
+                if let Ok(result) = receiver.try_recv() {
+                    obj.handle_update_result(result);
+                    glib::ControlFlow::Break
+                } else {
+                    glib::ControlFlow::Continue
                 }
+            })
+        );
+    }
+
+    fn handle_update_result(&self, result: Result<Vec<PendingUpdate>, String>) {
+        let imp = self.imp();
+
+        imp.update_list.remove_css_class("stale");
+        let updates = match result {
+            Ok(updates) => updates,
+            Err(e) => {
+                eprintln!("Failed to check for updates: {}", e);
+                self.clear_list();
+                imp.last_error.replace(e);
+                self.transition(StateEvent::CheckFailed("Checking for updates failed, read the log".to_string()));
+                return;
             }
+        };
+
+        self.set_updates(&updates);
+        if !updates.is_empty() {
+            self.refresh_prefetch();
         }
+        self.update_store(|store| store.record_check(&updates));
+        UpdateManager::report_check(updates.clone());
+        self.transition(StateEvent::CheckFinished(updates.len() as u32));
+        self.settle_reboot();
+        self.warn_stale_upgrade();
     }
 
 }
+
+fn last_checked(time: i64) -> Option<String> {
+    if time <= 0 {
+        return None;
+    }
+    let checked = glib::DateTime::from_unix_local(time).ok()?;
+    let today = glib::DateTime::now_local().ok()?.ymd() == checked.ymd();
+    let format = if today { "Last checked today at %H:%M" } else { "Last checked on %x at %H:%M" };
+    checked.format(format).ok().map(|text| text.to_string())
+}
@@ -0,0 +1,41 @@
+#!/usr/bin/env python3
+# Stand-in for a fleet endpoint. Appends every request it receives to a log
+# as one JSON object per line, and answers the first few with 503 to
+# exercise the retries.
+#
+#   tests/fleet-server.py <port> <log> [failures]
+
+import json
+import sys
+from http.server import BaseHTTPRequestHandler, HTTPServer
+
+port = int(sys.argv[1])
+log = sys.argv[2]
+failures = int(sys.argv[3]) if len(sys.argv) > 3 else 0
+
+
+class Handler(BaseHTTPRequestHandler):
+    def do_POST(self):
+        global failures
+        body = self.rfile.read(int(self.headers.get("Content-Length", 0)))
+        status = 503 if failures > 0 else 204
+        failures = max(failures - 1, 0)
+
+        with open(log, "a") as file:
+            file.write(json.dumps({
+                "path": self.path,
+                "status": status,
+                "authorization": self.headers.get("Authorization"),
+                "schema": self.headers.get("X-Updater-Schema"),
+                "content_type": self.headers.get("Content-Type"),
+                "body": json.loads(body),
+            }) + "\n")
+
+        self.send_response(status)
+        self.end_headers()
+
+    def log_message(self, format, *args):
+        pass
+
+
+HTTPServer(("127.0.0.1", port), Handler).serve_forever()
@@ -0,0 +1,62 @@
+#!/bin/sh
+# Sends a fleet report for the `updates` scenario to a local stand-in
+# endpoint and checks what arrived. The first request is refused, so the
+# report only gets through on the retry.
+#
+#   tests/run-fleet.sh [path/to/updater-new]
+
+set -e
+tests=$(cd "$(dirname "$0")" && pwd)
+binary=${1:-$tests/../target/debug/updater-new}
+port=${UPDATER_NEW_FLEET_PORT:-8765}
+
+work=$(mktemp -d)
+trap 'kill $server 2>/dev/null; rm -rf "$work"' EXIT
+
+# Settings and state of a throwaway user, the real ones stay untouched.
+mkdir -p "$work/schemas" "$work/config/glib-2.0/settings"
+glib-compile-schemas --targetdir="$work/schemas" "$tests/../data"
+cat > "$work/config/glib-2.0/settings/keyfile" <<EOF
+[org/gnome/Example]
+fleet-endpoint='http://127.0.0.1:$port/report'
+fleet-token='test-token'
+EOF
+export GSETTINGS_SCHEMA_DIR="$work/schemas"
+export GSETTINGS_BACKEND=keyfile
+export XDG_CONFIG_HOME="$work/config"
+export XDG_STATE_HOME="$work/state"
+
+export UPDATER_NEW_SCENARIO="$tests/scenarios/updates"
+export UPDATER_NEW_FAKE_BIN="$tests/fake-bin"
+export UPDATER_NEW_CALLS="$work/calls.log"
+export PATH="$tests/fake-bin:$PATH"
+
+python3 "$tests/fleet-server.py" "$port" "$work/requests.log" 1 &
+server=$!
+sleep 1
+
+"$binary" --fleet-report check
+
+python3 - "$work/requests.log" <<'EOF'
+import json
+import sys
+
+requests = [json.loads(line) for line in open(sys.argv[1])]
+assert [request["status"] for request in requests] == [503, 204], requests
+request = requests[-1]
+assert request["path"] == "/report"
+assert request["authorization"] == "Bearer test-token"
+assert request["schema"] == "1"
+assert request["content_type"] == "application/json"
+
+body = request["body"]
+assert body["schema_version"] == 1
+assert body["event"] == "check"
+packages = body["report"]["packages"]
+assert body["report"]["kind"] == "pending" and len(packages) == 3, packages
+assert all(package["result"] == "pending" for package in packages)
+print("fleet report received:", ", ".join(package["name"] for package in packages))
+EOF
+
+# Only the lock file is left.
+test -z "$(ls "$work/state/updater-new/fleet-outbox")"
@@ -0,0 +1,196 @@
+#!/bin/sh
+# Drives the window through the recorded scenarios: waits for the check,
+# updates a row or everything through the window actions and checks the
+# state the window ends up in and the commands it ran. The window shows up
+# on a broadway display nobody looks at, and talks on its own session bus.
+#
+#   tests/run-flows.sh [path/to/updater-new]
+#
+# Needs gdbus, dbus-run-session, gtk4-broadwayd and python-dbusmock.
+
+set -e
+tests=$(cd "$(dirname "$0")" && pwd)
+binary=${1:-$tests/../target/debug/updater-new}
+
+if [ -z "$UPDATER_NEW_FLOWS_BUS" ]; then
+    UPDATER_NEW_FLOWS_BUS=1 exec dbus-run-session -- "$0" "$@"
+fi
+
+work=$(mktemp -d)
+trap 'kill $app $broadway $upower 2>/dev/null; rm -rf "$work"' EXIT
+
+# Settings and state of a throwaway user, the real ones stay untouched.
+mkdir -p "$work/schemas"
+glib-compile-schemas --targetdir="$work/schemas" "$tests/../data"
+export GSETTINGS_SCHEMA_DIR="$work/schemas"
+export GSETTINGS_BACKEND=keyfile
+
+export UPDATER_NEW_FAKE_BIN="$tests/fake-bin"
+# The fake uname's kernel, so the host's kernel can't make it RebootRequired.
+export UPDATER_NEW_FAKE_MODULES="$tests/modules"
+export UPDATER_NEW_CALLS="$work/calls.log"
+export PATH="$tests/fake-bin:$PATH"
+# Always online, whatever the machine running the tests is connected to.
+export GIO_USE_NETWORK_MONITOR=base
+
+display=${UPDATER_NEW_BROADWAY_DISPLAY:-:5}
+gtk4-broadwayd "$display" >/dev/null 2>&1 &
+broadway=$!
+export GDK_BACKEND=broadway BROADWAY_DISPLAY="$display"
+
+app_id=org.gnome.Example
+app_path=/org/gnome/Example
+
+property() {
+    gdbus call --session --dest $app_id --object-path $app_path \
+        --method org.freedesktop.DBus.Properties.Get org.gnome.Example.Updater "$1" 2>/dev/null
+}
+
+activate() {
+    gdbus call --session --dest $app_id --object-path "$1" \
+        --method org.gtk.Actions.Activate "$2" "$3" '{}' >/dev/null
+}
+
+# Waits up to 30 s for the State property, e.g. (<'up-to-date'>,).
+wait_for() {
+    tries=150
+    while [ "$(property State)" != "(<'$1'>,)" ]; do
+        tries=$((tries - 1))
+        if [ $tries = 0 ]; then
+            echo "expected state $1, got $(property State)" >&2
+            return 1
+        fi
+        sleep 0.2
+    done
+}
+
+wait_for_call() {
+    tries=150
+    while ! grep -q -- "$1" "$UPDATER_NEW_CALLS"; do
+        tries=$((tries - 1))
+        if [ $tries = 0 ]; then
+            echo "expected a call matching '$1' in:" >&2
+            cat "$UPDATER_NEW_CALLS" >&2
+            return 1
+        fi
+        sleep 0.2
+    done
+}
+
+# wait_for_calls <pattern> <count>: until that many calls match.
+wait_for_calls() {
+    tries=150
+    while [ "$(grep -c -- "$1" "$UPDATER_NEW_CALLS")" -lt "$2" ]; do
+        tries=$((tries - 1))
+        if [ $tries = 0 ]; then
+            echo "expected $2 calls matching '$1' in:" >&2
+            cat "$UPDATER_NEW_CALLS" >&2
+            return 1
+        fi
+        sleep 0.2
+    done
+}
+
+# start <scenario>: a fresh window on a fresh configuration, checked once.
+start() {
+    echo "== $1: $2"
+    rm -rf "$work/config" "$work/state"
+    export XDG_CONFIG_HOME="$work/config" XDG_STATE_HOME="$work/state"
+    export UPDATER_NEW_SCENARIO="$tests/scenarios/$1"
+    : > "$UPDATER_NEW_CALLS"
+    "$binary" >"$work/app.log" 2>&1 &
+    app=$!
+}
+
+stop() {
+    activate $app_path quit '[]'
+    wait $app || true
+}
+
+start no-updates "checkupdates exits with 2"
+wait_for up-to-date
+wait_for_call '^checkupdates'
+stop
+
+start check-failed "checkupdates fails"
+wait_for failed
+test "$(property Error)" = "(<'Checking for updates failed, read the log'>,)"
+stop
+
+start updates "update all"
+wait_for updates-available
+test "$(property PendingUpdates)" = "(<uint32 3>,)"
+activate $app_path/window/1 update-all '[]'
+wait_for_call '^pkexec pacman -Syu'
+grep -q '^answered: y' "$UPDATER_NEW_CALLS"
+# The list is checked again afterwards, the scenario still lists the same three.
+wait_for_calls '^checkupdates' 2
+wait_for updates-available
+stop
+
+start updates "update one row"
+wait_for updates-available
+activate $app_path/window/1 update-package "[<'vim'>]"
+wait_for_call '^pkexec pacman -y -S .*vim$'
+if grep -q '^pkexec pacman -Syu' "$UPDATER_NEW_CALLS"; then
+    echo "updating one row upgraded everything" >&2
+    exit 1
+fi
+# The row's update went through the state machine, the list is checked again.
+wait_for_calls '^checkupdates' 2
+wait_for updates-available
+stop
+
+start conflicting-files "update all fails"
+wait_for updates-available
+activate $app_path/window/1 update-all '[]'
+wait_for failed
+test "$(property Error)" = "(<'Update Failed, 2 conflicting files'>,)"
+stop
+
+start unknown-question "pacman asks something the app doesn't know"
+wait_for updates-available
+activate $app_path/window/1 update-all '[]'
+wait_for failed
+# Nothing was typed, pacman got the end of its input instead.
+grep -q '^answered: $' "$UPDATER_NEW_CALLS"
+test "$(property Error)" = "(<'Update Failed, the update failed'>,)"
+stop
+
+# auto_update <OnBattery>: one run of the timer's `--auto-update` against a
+# stand-in UPower on the session bus, see src/conditions.rs.
+auto_update() {
+    echo "== automatic update, on battery: $1"
+    rm -rf "$work/config" "$work/state"
+    mkdir -p "$work/config/glib-2.0/settings"
+    cat > "$work/config/glib-2.0/settings/keyfile" <<EOF
+[org/gnome/Example]
+auto-update=true
+maintenance-window='Daily 00:00-23:59'
+auto-update-skip-on-news=false
+EOF
+    export XDG_CONFIG_HOME="$work/config" XDG_STATE_HOME="$work/state"
+    export UPDATER_NEW_SCENARIO="$tests/scenarios/updates"
+    : > "$UPDATER_NEW_CALLS"
+
+    python3 -m dbusmock --session --template upower --parameters "{\"OnBattery\": $1}" >/dev/null 2>&1 &
+    upower=$!
+    gdbus wait --session --timeout 10 org.freedesktop.UPower
+    UPDATER_UPOWER_BUS=session "$binary" --auto-update >"$work/auto-update.log" 2>&1
+    kill $upower
+    wait $upower || true
+}
+
+auto_update true
+grep -q 'Automatic update deferred: running on battery' "$work/auto-update.log"
+if grep -q . "$UPDATER_NEW_CALLS"; then
+    echo "the deferred automatic update still ran:" >&2
+    cat "$UPDATER_NEW_CALLS" >&2
+    exit 1
+fi
+
+auto_update false
+grep -q '^checkupdates' "$UPDATER_NEW_CALLS"
+grep -q -- '--auto-upgrade-helper' "$UPDATER_NEW_CALLS"
+
+echo "all flows passed"
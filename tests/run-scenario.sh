@@ -0,0 +1,28 @@
+#!/bin/sh
+# Starts the app against one of the recorded scenarios, with the fake
+# checkupdates, pacman and pkexec from tests/fake-bin instead of the real ones.
+#
+#   tests/run-scenario.sh updates [path/to/updater-new]
+#
+# Every faked call is appended to $UPDATER_NEW_CALLS, /tmp/updater-new-calls.log
+# unless set.
+
+set -e
+tests=$(cd "$(dirname "$0")" && pwd)
+scenario=${1:?usage: $0 <scenario> [binary]}
+binary=${2:-$tests/../target/debug/updater-new}
+
+if [ ! -d "$tests/scenarios/$scenario" ]; then
+    echo "No scenario $scenario, available: $(ls "$tests/scenarios" | tr '\n' ' ')" >&2
+    exit 1
+fi
+
+export UPDATER_NEW_SCENARIO="$tests/scenarios/$scenario"
+export UPDATER_NEW_FAKE_BIN="$tests/fake-bin"
+export UPDATER_NEW_FAKE_MODULES="$tests/modules"
+export UPDATER_NEW_CALLS=${UPDATER_NEW_CALLS:-/tmp/updater-new-calls.log}
+# Commands run through pkexec and sh are looked up in $PATH.
+export PATH="$tests/fake-bin:$PATH"
+: > "$UPDATER_NEW_CALLS"
+
+exec "$binary"